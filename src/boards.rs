@@ -1 +1,39 @@
-pub mod quty;
+pub mod breakout;
+pub mod described;
+pub mod description;
+
+use crate::devices::Device;
+use crate::events::Events;
+
+pub trait Board {
+    fn mcu(&self) -> &Device;
+    fn mcu_mut(&mut self) -> &mut Device;
+
+    // Advance by one MCU clock, returns the step time or 0 on termination
+    fn step(&mut self) -> u64;
+    fn events(&mut self, events: Events);
+
+    fn contention(&self) -> bool {
+        false
+    }
+
+    fn core_debug(&mut self) {
+        self.mcu_mut().core.debug(true);
+    }
+
+    fn core_dumpregs(&self) {
+        self.mcu().dump_regs();
+    }
+
+    fn mcu_dumpstack(&self) {
+        self.mcu().dump_stack();
+    }
+
+    fn mcu_programme(&mut self, filename: &str) {
+        self.mcu_mut().load_hex(filename);
+    }
+
+    fn mcu_write_stdout(&self) {
+        self.mcu().stdio.borrow().out_close();
+    }
+}
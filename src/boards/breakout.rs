@@ -0,0 +1,69 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::devices::{Device, DeviceType};
+use crate::nets::Net;
+
+use crate::events::Events;
+
+use super::Board;
+
+// Bare microcontroller with every bonded pin on its own net, named after the pin
+pub struct Breakout {
+    nets: Vec<Rc<RefCell<Net>>>,
+    mcu: Device,
+    time: u64,
+}
+
+impl Breakout {
+    pub fn new(dt: DeviceType) -> Self {
+        let mcu = Device::new(dt);
+
+        let mut nets = Vec::new();
+        for (port, &pins) in mcu.pins.iter().enumerate() {
+            for pin in 0..pins {
+                let name = format!("P{}{}", (b'A' + port as u8) as char, pin);
+                let net = Rc::new(RefCell::new(Net::new(name)));
+                mcu.ports[port].borrow_mut().connect(pin, Rc::clone(&net));
+                nets.push(net);
+            }
+        }
+
+        let mut breakout = Breakout { nets, mcu, time: 0 };
+
+        for net in &breakout.nets {
+            net.borrow_mut().update(0);
+        }
+        breakout.mcu.update(0);
+
+        breakout
+    }
+}
+
+impl Board for Breakout {
+    fn mcu(&self) -> &Device {
+        &self.mcu
+    }
+
+    fn mcu_mut(&mut self) -> &mut Device {
+        &mut self.mcu
+    }
+
+    fn step(&mut self) -> u64 {
+        let timestep = self.mcu.tick(self.time);
+        for net in &self.nets {
+            net.borrow_mut().update(self.time);
+        }
+        self.mcu.update(self.time);
+
+        self.time += timestep;
+
+        timestep
+    }
+
+    fn events(&mut self, events: Events) {
+        if !events.is_empty() {
+            println!("[EVENTS] The breakout board has no hardware, events ignored.");
+        }
+    }
+}
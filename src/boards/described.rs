@@ -0,0 +1,115 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::devices::Device;
+use crate::nets::Net;
+
+use crate::hardware::Hardware;
+
+use crate::events::Events;
+use crate::CLI;
+
+use super::description::BoardDescription;
+use super::Board;
+
+// Board built from a description of its wiring, such as the bundled QUTy
+pub struct DescribedBoard {
+    hw: HashMap<String, Box<dyn Hardware>>,
+    nets: HashMap<String, Rc<RefCell<Net>>>,
+    mcu: Device,
+    time: u64,
+    events: Events,
+    contention: bool,
+}
+
+impl DescribedBoard {
+    pub fn new(description: &BoardDescription) -> Result<Self, String> {
+        let mcu = Device::new(description.device_type()?);
+        let (nets, hw) = description.build(&mcu)?;
+
+        let mut board = DescribedBoard {
+            hw,
+            nets,
+            mcu,
+            time: 0,
+            events: Vec::new(),
+            contention: false,
+        };
+
+        for net in &board.nets {
+            net.1.borrow_mut().update(0);
+        }
+        for dev in &mut board.hw {
+            dev.1.update(0);
+        }
+        board.mcu.update(0);
+
+        Ok(board)
+    }
+}
+
+impl Board for DescribedBoard {
+    fn mcu(&self) -> &Device {
+        &self.mcu
+    }
+
+    fn mcu_mut(&mut self) -> &mut Device {
+        &mut self.mcu
+    }
+
+    fn step(&mut self) -> u64 {
+        // let timestep = 300; // Default 300 ns => 3.3 MHz
+
+        if !self.events.is_empty() {
+            while self.time >= self.events[0].time {
+                match self.hw.get_mut(&self.events[0].device) {
+                    Some(hw) => hw.event(self.time, &self.events[0].event),
+                    None => println!(
+                        "[EVENTS] No device {} on this board, event ignored.",
+                        self.events[0].device
+                    ),
+                }
+                self.events.remove(0);
+                if self.events.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        let mut timestep = self.mcu.tick(self.time);
+        for net in &self.nets {
+            net.1.borrow_mut().update(self.time);
+        }
+        if CLI.fail_on_contention && self.nets.values().any(|net| net.borrow().contention) {
+            self.contention = true;
+            timestep = 0;
+        }
+        for hw in &mut self.hw {
+            hw.1.update(self.time);
+        }
+        self.mcu.update(self.time);
+
+        // Update the time at the end (once we know what the micro step was)
+        self.time += timestep;
+
+        // Force UART flush if program terminates before event is called
+        if timestep == 0 {
+            for e in self.events.iter().filter(|e| e.event == "flush") {
+                if let Some(hw) = self.hw.get_mut(&e.device) {
+                    hw.event(self.time, "flush");
+                }
+            }
+        }
+
+        timestep
+    }
+
+    fn contention(&self) -> bool {
+        self.contention
+    }
+
+    fn events(&mut self, events: Events) {
+        self.events = events;
+    }
+}
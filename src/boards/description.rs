@@ -0,0 +1,347 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::rc::Rc;
+
+use serde::Deserialize;
+
+use crate::devices::{Device, DeviceType};
+use crate::nets::{Net, NetState, PinState, VDD};
+
+use crate::hardware::display::Display;
+use crate::hardware::hostspi::HostSPI;
+use crate::hardware::ic74hc595::IC74HC595;
+use crate::hardware::led::Led;
+use crate::hardware::pot::Pot;
+use crate::hardware::pushbutton::Pushbutton;
+use crate::hardware::resistor::Resistor;
+use crate::hardware::sinkpwm::SinkPwm;
+use crate::hardware::sinkuart::SinkUART;
+#[cfg(unix)]
+use crate::hardware::uartbridge::PtyBridge;
+use crate::hardware::uartbridge::{StdioBridge, TcpBridge};
+use crate::hardware::Hardware;
+use crate::CLI;
+
+pub type Nets = HashMap<String, Rc<RefCell<Net>>>;
+pub type Components = HashMap<String, Box<dyn Hardware>>;
+
+// Declarative board layout: nets, MCU pin connections and components
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardDescription {
+    pub name: String,
+    #[serde(default = "default_mcu")]
+    pub mcu: String,
+    #[serde(default)]
+    pub nets: Vec<String>,
+    #[serde(default)]
+    pub pins: BTreeMap<String, String>,
+    #[serde(default, rename = "component")]
+    pub components: Vec<Component>,
+}
+
+fn default_mcu() -> String {
+    "ATtiny1626".to_string()
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Pull {
+    #[default]
+    Open,
+    Up,
+    Down,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Component {
+    Led {
+        name: String,
+        net: String,
+        #[serde(default)]
+        active_high: bool,
+    },
+    Pushbutton {
+        name: String,
+        net: String,
+        #[serde(default)]
+        active_high: bool,
+    },
+    Pot {
+        name: String,
+        net: String,
+        #[serde(default = "default_position")]
+        position: f32,
+    },
+    Resistor {
+        name: String,
+        net: String,
+        #[serde(default)]
+        volts: f32,
+        ohms: f32,
+    },
+    SinkPwm {
+        name: String,
+        label: Option<String>,
+        variant: String,
+        net: String,
+        #[serde(default)]
+        pull: Pull,
+    },
+    SinkUart {
+        name: String,
+        rx: String,
+        tx: String,
+        file: String,
+        #[serde(default)]
+        bridge: bool,
+    },
+    HostSpi {
+        name: String,
+        sck: String,
+        mosi: String,
+        miso: String,
+        ss: String,
+    },
+    IC74HC595 {
+        name: String,
+        q: [String; 8],
+        ds: String,
+        shcp: String,
+        stcp: String,
+        oe_n: String,
+        mr_n: String,
+    },
+    Display {
+        name: String,
+        segs: [String; 7],
+        en: String,
+        digit: String,
+    },
+}
+
+fn default_position() -> f32 {
+    0.5
+}
+
+impl BoardDescription {
+    pub fn quty() -> Self {
+        Self::parse(include_str!("quty.toml")).unwrap()
+    }
+
+    pub fn from_file(filename: &str) -> Result<Self, String> {
+        let s = fs::read_to_string(filename)
+            .map_err(|e| format!("Couldn't open {}. {}", filename, e))?;
+        Self::parse(&s).map_err(|e| format!("Couldn't parse {}. {}", filename, e))
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        toml::from_str(s).map_err(|e| e.to_string())
+    }
+
+    pub fn device_type(&self) -> Result<DeviceType, String> {
+        DeviceType::from_name(&self.mcu).ok_or(format!("Unsupported microcontroller {}.", self.mcu))
+    }
+
+    pub fn build(&self, mcu: &Device) -> Result<(Nets, Components), String> {
+        let mut nets: Nets = HashMap::new();
+        for name in &self.nets {
+            if nets.contains_key(name) | (name == "GND") | (name == "VDD") {
+                return Err(format!("Net {} is declared more than once.", name));
+            }
+            nets.insert(name.clone(), Rc::new(RefCell::new(Net::new(name.clone()))));
+        }
+
+        // Supply nets hold their state and are never updated
+        let net_gnd = Rc::new(RefCell::new(Net::new("GND".to_string())));
+        let net_vdd = Rc::new(RefCell::new(Net::new("VDD".to_string())));
+        net_gnd.borrow_mut().state = NetState::Low;
+        net_vdd.borrow_mut().state = NetState::High;
+        net_vdd.borrow_mut().voltage = VDD;
+
+        let net = |name: &str| -> Result<Rc<RefCell<Net>>, String> {
+            match name {
+                "GND" => Ok(Rc::clone(&net_gnd)),
+                "VDD" => Ok(Rc::clone(&net_vdd)),
+                _ => nets
+                    .get(name)
+                    .map(Rc::clone)
+                    .ok_or(format!("Net {} is not declared.", name)),
+            }
+        };
+
+        for (pin, name) in &self.pins {
+            let (port, index) = Self::pin(mcu, pin)?;
+            mcu.ports[port].borrow_mut().connect(index, net(name)?);
+        }
+
+        let mut hw: Components = HashMap::new();
+        for component in &self.components {
+            let (name, part): (&String, Box<dyn Hardware>) = match component {
+                Component::Led {
+                    name,
+                    net: n,
+                    active_high,
+                } => (
+                    name,
+                    Box::new(Led::new(name.clone(), *active_high, net(n)?)),
+                ),
+                Component::Pushbutton {
+                    name,
+                    net: n,
+                    active_high,
+                } => (
+                    name,
+                    Box::new(Pushbutton::new(name.clone(), *active_high, net(n)?)),
+                ),
+                Component::Pot {
+                    name,
+                    net: n,
+                    position,
+                } => (name, Box::new(Pot::new(name.clone(), net(n)?, *position))),
+                Component::Resistor {
+                    name,
+                    net: n,
+                    volts,
+                    ohms,
+                } => (
+                    name,
+                    Box::new(Resistor::new(name.clone(), net(n)?, *volts, *ohms)),
+                ),
+                Component::SinkPwm {
+                    name,
+                    label,
+                    variant,
+                    net: n,
+                    pull,
+                } => {
+                    let pinstate = match pull {
+                        Pull::Open => PinState::Open,
+                        Pull::Up => PinState::WeakPullUp,
+                        Pull::Down => PinState::WeakPullDown,
+                    };
+                    (
+                        name,
+                        Box::new(SinkPwm::new(
+                            label.clone().unwrap_or(name.clone()),
+                            variant.clone(),
+                            net(n)?,
+                            pinstate,
+                        )),
+                    )
+                }
+                Component::SinkUart {
+                    name,
+                    rx,
+                    tx,
+                    file,
+                    bridge,
+                } => {
+                    let mut uart = SinkUART::new(name.clone(), net(rx)?, net(tx)?, file.clone());
+                    if *bridge {
+                        Self::bridge(name, &mut uart);
+                    }
+                    (name, Box::new(uart))
+                }
+                Component::HostSpi {
+                    name,
+                    sck,
+                    mosi,
+                    miso,
+                    ss,
+                } => (
+                    name,
+                    Box::new(HostSPI::new(
+                        name.clone(),
+                        net(sck)?,
+                        net(mosi)?,
+                        net(miso)?,
+                        net(ss)?,
+                    )),
+                ),
+                Component::IC74HC595 {
+                    name,
+                    q,
+                    ds,
+                    shcp,
+                    stcp,
+                    oe_n,
+                    mr_n,
+                } => {
+                    let mut sr = IC74HC595::new(name.clone());
+                    for (i, n) in q.iter().enumerate() {
+                        sr.connect_q(i, net(n)?);
+                    }
+                    sr.connect("ds", net(ds)?);
+                    sr.connect("shcp", net(shcp)?);
+                    sr.connect("stcp", net(stcp)?);
+                    sr.connect("oe_n", net(oe_n)?);
+                    sr.connect("mr_n", net(mr_n)?);
+                    (name, Box::new(sr))
+                }
+                Component::Display {
+                    name,
+                    segs,
+                    en,
+                    digit,
+                } => {
+                    let mut disp = Display::new(name.clone());
+                    for (i, n) in segs.iter().enumerate() {
+                        disp.connect_seg(i, net(n)?);
+                    }
+                    disp.connect("en", net(en)?);
+                    disp.connect("digit", net(digit)?);
+                    (name, Box::new(disp))
+                }
+            };
+            if hw.insert(name.clone(), part).is_some() {
+                return Err(format!("Component {} is declared more than once.", name));
+            }
+        }
+
+        Ok((nets, hw))
+    }
+
+    fn pin(mcu: &Device, pin: &str) -> Result<(usize, u8), String> {
+        // Pins are named Pxn, e.g. PA1
+        let chars: Vec<char> = pin.chars().collect();
+        if let ['P', port @ 'A'..='Z', index @ '0'..='7'] = chars[..] {
+            let port = port as usize - 'A' as usize;
+            let index = index as u8 - b'0';
+            if (port < mcu.ports.len()) && (index < mcu.pins[port]) {
+                return Ok((port, index));
+            }
+        }
+        Err(format!("{} is not a valid pin.", pin))
+    }
+
+    fn bridge(name: &str, uart: &mut SinkUART) {
+        if CLI.uart_stdio {
+            uart.set_bridge(Box::new(StdioBridge::new()));
+        } else if let Some(port) = CLI.uart_tcp {
+            match TcpBridge::new(port, CLI.uart_tcp_wait) {
+                Ok(tcp) => uart.set_bridge(Box::new(tcp)),
+                Err(e) => println!(
+                    "[WARNING] Unable to serve {} on port {}: {}.",
+                    name, port, e
+                ),
+            }
+        } else if CLI.uart_pty {
+            #[cfg(unix)]
+            match PtyBridge::new() {
+                Ok(pty) => {
+                    println!("[UART] {} connected to {}.", name, pty.path);
+                    uart.set_bridge(Box::new(pty));
+                }
+                Err(e) => println!(
+                    "[WARNING] Unable to open a pseudo-terminal for {}: {}.",
+                    name, e
+                ),
+            }
+            #[cfg(not(unix))]
+            println!("[WARNING] Pseudo-terminals are not supported on this platform.");
+        }
+    }
+}
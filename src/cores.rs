@@ -2,6 +2,9 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use super::memory::MemoryMapped;
+use crate::peripherals::slpctrl::SleepMode;
+
+mod des;
 
 use bitmatch::bitmatch;
 use bitvec::prelude::*;
@@ -14,8 +17,23 @@ pub trait InterruptHandler {
     fn reti(&mut self) {}
 }
 
+pub trait SleepController {
+    // Returns true if the SLEEP instruction puts the core to sleep
+    fn sleep(&mut self) -> bool {
+        false
+    }
+
+    fn wake(&mut self) {}
+
+    // Current sleep mode, None while the core is awake
+    fn mode(&self) -> Option<SleepMode> {
+        None
+    }
+}
+
 #[allow(dead_code)]
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum CoreType {
     AVR,
     AVRe,
@@ -25,18 +43,39 @@ pub enum CoreType {
     AVRrc,
 }
 
+impl CoreType {
+    // Classic cores map the register file into data space, with I/O space at 0x20
+    fn classic(&self) -> bool {
+        matches!(self, CoreType::AVR | CoreType::AVRe | CoreType::AVReplus)
+    }
+
+    fn io_offset(&self) -> u32 {
+        if self.classic() {
+            0x20
+        } else {
+            0x00
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub struct Core {
     variant: CoreType,
     regs: [u8; 32],
     sreg: u8,
-    pc: u16,
+    pc: u32,
+    pc_wide: bool,  // 22-bit PC and EIND for more than 128 KB of flash
+    extended: bool, // RAMPZ for more than 64 KB of flash
     sp: u16,
+    rampz: u8,
+    eind: u8,
     pub ds: Rc<RefCell<dyn MemoryMapped>>,
     pub progmem: Rc<RefCell<dyn MemoryMapped>>,
     busy: u8,
     interrupt_handler: Rc<RefCell<dyn InterruptHandler>>,
     interupt_inhibit: bool,
+    sleep_controller: Option<Rc<RefCell<dyn SleepController>>>,
+    sleeping: bool,
     debug: bool,
 }
 
@@ -46,18 +85,27 @@ impl Core {
         ds: Rc<RefCell<dyn MemoryMapped>>,
         progmem: Rc<RefCell<dyn MemoryMapped>>,
         interrupt_handler: Rc<RefCell<dyn InterruptHandler>>,
+        sleep_controller: Option<Rc<RefCell<dyn SleepController>>>,
         sp: u16,
     ) -> Self {
+        let pc_wide = progmem.borrow().get_size() > 128 * 1024;
+        let extended = progmem.borrow().get_size() > 64 * 1024;
         Self {
             variant,
             regs: [0; 32],
             sreg: 0,
             pc: 0,
+            pc_wide,
+            extended,
             sp,
+            rampz: 0,
+            eind: 0,
             ds,
             progmem,
             interrupt_handler,
             interupt_inhibit: false,
+            sleep_controller,
+            sleeping: false,
             busy: 0,
             debug: false,
         }
@@ -92,50 +140,48 @@ impl Core {
     }
 
     fn get_io_register(&self, register: u8) -> u8 {
-        match register {
-            0x3F => self.sreg,              // CPU.SREG
-            0x3E => (self.sp >> 8) as u8,   // CPU.SPH
-            0x3D => (self.sp & 0xFF) as u8, // CPU.SPL
-            _ => self.ds.borrow_mut().read(usize::from(register)).0,
-        }
+        self.get_data_space(u32::from(register) + self.variant.io_offset())
+            .0
     }
 
     fn set_io_register(&mut self, register: u8, value: u8) {
-        match register {
-            0x3F => self.sreg = value,                                    // CPU.SREG
-            0x3E => self.sp = (self.sp & 0x00FF) | ((value as u16) << 8), // CPU.SPH
-            0x3D => self.sp = (self.sp & 0xFF00) | (value as u16),        // CPU.SPL
-            _ => {
-                self.ds.borrow_mut().write(usize::from(register), value);
-            }
-        }
+        self.set_data_space(u32::from(register) + self.variant.io_offset(), value);
     }
 
-    fn get_data_space(&self, address: u32) -> u8 {
-        match address {
-            0x0000003F => self.sreg,              // CPU.SREG
-            0x0000003E => (self.sp >> 8) as u8,   // CPU.SPH
-            0x0000003D => (self.sp & 0xFF) as u8, // CPU.SPL
-            _ => {
-                self.ds
-                    .borrow_mut()
-                    .read(usize::try_from(address).unwrap())
-                    .0
-            }
+    // Data space accesses return the latency of the memory or peripheral in cycles
+    fn get_data_space(&self, address: u32) -> (u8, usize) {
+        if self.variant.classic() && address < 0x20 {
+            return (self.regs[address as usize], 0);
+        }
+        match address - self.variant.io_offset() {
+            0x0000003F => (self.sreg, 0),                   // CPU.SREG
+            0x0000003E => ((self.sp >> 8) as u8, 0),        // CPU.SPH
+            0x0000003D => ((self.sp & 0xFF) as u8, 0),      // CPU.SPL
+            0x0000003C if self.pc_wide => (self.eind, 0),   // CPU.EIND
+            0x0000003B if self.extended => (self.rampz, 0), // CPU.RAMPZ
+            _ => self.ds.borrow_mut().read(usize::try_from(address).unwrap()),
         }
     }
 
-    fn set_data_space(&mut self, address: u32, value: u8) {
-        match address {
+    fn set_data_space(&mut self, address: u32, value: u8) -> usize {
+        if self.variant.classic() && address < 0x20 {
+            self.regs[address as usize] = value;
+            return 0;
+        }
+        match address - self.variant.io_offset() {
             0x0000003F => self.sreg = value, // CPU.SREG
             0x0000003E => self.sp = (self.sp & 0x00FF) | ((value as u16) << 8), // CPU.SPH
             0x0000003D => self.sp = (self.sp & 0xFF00) | (value as u16), // CPU.SPL
+            0x0000003C if self.pc_wide => self.eind = value, // CPU.EIND
+            0x0000003B if self.extended => self.rampz = value, // CPU.RAMPZ
             _ => {
-                self.ds
+                return self
+                    .ds
                     .borrow_mut()
                     .write(usize::try_from(address).unwrap(), value);
             }
         }
+        0
     }
 
     fn get_ps(&self, address: u32) -> u8 {
@@ -152,6 +198,41 @@ impl Core {
             .0
     }
 
+    fn pc_mask(&self) -> u32 {
+        if self.pc_wide {
+            0x3FFFFF
+        } else {
+            0xFFFF
+        }
+    }
+
+    // Return addresses are pushed low byte first, with a third byte for a 22-bit PC
+    fn push_pc(&mut self, pc: u32) {
+        let mut ds = self.ds.borrow_mut();
+        ds.write(usize::from(self.sp), pc as u8);
+        self.sp -= 1;
+        ds.write(usize::from(self.sp), (pc >> 8) as u8);
+        self.sp -= 1;
+        if self.pc_wide {
+            ds.write(usize::from(self.sp), (pc >> 16) as u8);
+            self.sp -= 1;
+        }
+    }
+
+    fn pop_pc(&mut self) -> u32 {
+        let mut ds = self.ds.borrow_mut();
+        let mut pc = 0u32;
+        if self.pc_wide {
+            self.sp += 1;
+            pc = u32::from(ds.read(usize::from(self.sp)).0) << 16;
+        }
+        self.sp += 1;
+        let (bh, _) = ds.read(usize::from(self.sp));
+        self.sp += 1;
+        let (bl, _) = ds.read(usize::from(self.sp));
+        pc | ((bh as u32) << 8) | (bl as u32)
+    }
+
     fn get_sreg_bit(&self, bit: BitSREG) -> bool {
         (self.sreg & (1 << bit as u8)) != 0
     }
@@ -710,23 +791,18 @@ impl Core {
     // FLOW CONTROL INSTRUCTIONS
     fn brbx(&mut self, s: u8, k: i8, set: bool) {
         if !set ^ self.get_sreg_bit(BitSREG::from(s)) {
-            let mut pc = self.pc as i32;
-            pc += i32::from(k);
-            self.pc = pc as u16;
+            self.pc = self.pc.wrapping_add(k as u32) & self.pc_mask();
             self.busy = 1;
         }
     }
 
     fn call(&mut self, k: u32) {
-        self.busy = 2; // AVRxt, 16-bit PC
+        // One more cycle to push a 22-bit PC
+        self.busy = if self.variant.classic() { 3 } else { 2 } + u8::from(self.pc_wide);
 
         // PC + 1 because we already incremented the PC
-        let mut ds = self.ds.borrow_mut();
-        ds.write(usize::from(self.sp), (self.pc + 1) as u8);
-        self.sp -= 1;
-        ds.write(usize::from(self.sp), ((self.pc + 1) >> 8) as u8);
-        self.sp -= 1;
-        self.pc = k as u16;
+        self.push_pc(self.pc + 1);
+        self.pc = k & self.pc_mask();
     }
 
     #[allow(non_snake_case)]
@@ -819,144 +895,149 @@ impl Core {
 
     #[allow(non_snake_case)]
     fn cpse(&mut self, d: u8, r: u8) {
-        use Instruction::*;
-
         let Rd = self.get_register(d);
         let Rr = self.get_register(r);
 
         if Rd == Rr {
-            let opcode = self.get_progmem(self.pc as u32);
-            let prefetch = self.get_progmem((self.pc + 1) as u32);
-            let op = Instruction::decode(opcode, prefetch);
-            match op {
-                CALL { .. } | JMP { .. } | LDS { .. } | STS { .. } => {
-                    self.pc += 2;
-                    self.busy = 2
-                }
-                _ => {
-                    self.pc += 1;
-                    self.busy = 1
-                }
-            }
+            self.skip();
         }
     }
 
-    fn icall(&mut self) {
-        self.busy = 1; // AVRxt, 16-bit PC
+    fn icall(&mut self, eind: bool) {
+        self.busy = match self.variant {
+            CoreType::AVRxm | CoreType::AVRxt => 1,
+            _ => 2,
+        } + u8::from(self.pc_wide);
 
         // PC + 0 because we already incremented the PC
-        let mut ds = self.ds.borrow_mut();
-        ds.write(usize::from(self.sp), (self.pc) as u8);
-        self.sp -= 1;
-        ds.write(usize::from(self.sp), ((self.pc) >> 8) as u8);
-        self.sp -= 1;
-        self.pc = self.get_register_word(30);
+        self.push_pc(self.pc);
+        self.pc = self.indirect(eind);
     }
 
-    fn ijmp(&mut self) {
+    fn ijmp(&mut self, eind: bool) {
         self.busy = 1;
 
-        self.pc = self.get_register_word(30);
+        self.pc = self.indirect(eind);
+    }
+
+    // Z, extended by EIND for EICALL and EIJMP
+    fn indirect(&self, eind: bool) -> u32 {
+        let z = u32::from(self.get_register_word(PointerRegister::Z as u8));
+        if eind {
+            ((u32::from(self.eind) << 16) | z) & self.pc_mask()
+        } else {
+            z
+        }
     }
 
     fn jmp(&mut self, k: u32) {
         self.busy = 2;
 
-        self.pc = k as u16;
+        self.pc = k & self.pc_mask();
     }
 
     fn rcall(&mut self, k: i16) {
-        self.busy = 1; // AVRxt, 16-bit PC
+        self.busy = match self.variant {
+            CoreType::AVRxm | CoreType::AVRxt => 1,
+            _ => 2,
+        } + u8::from(self.pc_wide);
 
         // PC + 0 because we already incremented the PC
-        let mut ds = self.ds.borrow_mut();
-        ds.write(usize::from(self.sp), (self.pc) as u8);
-        self.sp -= 1;
-        ds.write(usize::from(self.sp), ((self.pc) >> 8) as u8);
-        self.sp -= 1;
-        self.pc = self.pc.overflowing_add(k as u16).0;
+        self.push_pc(self.pc);
+        self.pc = self.pc.wrapping_add(k as u32) & self.pc_mask();
     }
 
     fn ret(&mut self) {
-        self.busy = 3; // 16-bit PC, not AVRrc
+        self.busy = if self.variant == CoreType::AVRrc {
+            5
+        } else {
+            3
+        } + u8::from(self.pc_wide);
 
-        let mut ds = self.ds.borrow_mut();
-        self.sp += 1;
-        let (bh, _) = ds.read(usize::from(self.sp));
-        self.sp += 1;
-        let (bl, _) = ds.read(usize::from(self.sp));
-        self.pc = ((bh as u16) << 8) | (bl as u16);
+        self.pc = self.pop_pc();
     }
 
     fn reti(&mut self) {
-        self.busy = 3; // 16-bit PC, not AVRrc
+        self.busy = if self.variant == CoreType::AVRrc {
+            5
+        } else {
+            3
+        } + u8::from(self.pc_wide);
 
-        let mut ds = self.ds.borrow_mut();
-        self.sp += 1;
-        let (bh, _) = ds.read(usize::from(self.sp));
-        self.sp += 1;
-        let (bl, _) = ds.read(usize::from(self.sp));
-        self.pc = ((bh as u16) << 8) | (bl as u16);
+        self.pc = self.pop_pc();
 
         // Ack interrupt
         self.interrupt_handler.borrow_mut().reti();
 
-        // I bit in SREG not set for AVRxt
+        // I bit in SREG is only cleared on entry, and set again here, without CPUINT/PMIC
+        if !matches!(self.variant, CoreType::AVRxm | CoreType::AVRxt) {
+            self.set_sreg_bit(BitSREG::I, true);
+        }
         self.interupt_inhibit = true; // prevent immediate servicing of another interrupt
     }
 
     fn rjmp(&mut self, k: i16) {
         self.busy = 1;
 
-        self.pc = self.pc.overflowing_add(k as u16).0;
+        self.pc = self.pc.wrapping_add(k as u32) & self.pc_mask();
     }
 
     #[allow(non_snake_case)]
     fn sbix(&mut self, A: u8, b: u8, set: bool) {
-        use Instruction::*;
-
         let bitval_n = (self.get_io_register(A) & (1 << b)) == 0;
 
         if set ^ bitval_n {
-            let opcode = self.get_progmem(self.pc as u32);
-            let prefetch = self.get_progmem((self.pc + 1) as u32);
-            let op = Instruction::decode(opcode, prefetch);
-            match op {
-                CALL { .. } | JMP { .. } | LDS { .. } | STS { .. } => {
-                    self.pc += 2;
-                    self.busy = 2
-                }
-                _ => {
-                    self.pc += 1;
-                    self.busy = 1
-                }
-            }
+            self.skip();
         }
     }
 
     #[allow(non_snake_case)]
     fn sbrx(&mut self, r: u8, b: u8, set: bool) {
-        use Instruction::*;
-
         let bitval_n = (self.get_register(r) & (1 << b)) == 0;
 
         if set ^ bitval_n {
-            let opcode = self.get_progmem(self.pc as u32);
-            let prefetch = self.get_progmem((self.pc + 1) as u32);
-            let op = Instruction::decode(opcode, prefetch);
-            match op {
-                CALL { .. } | JMP { .. } | LDS { .. } | STS { .. } => {
-                    self.pc += 2;
-                    self.busy = 2
-                }
-                _ => {
-                    self.pc += 1;
-                    self.busy = 1
-                }
+            self.skip();
+        }
+    }
+
+    // One extra cycle per word skipped
+    fn skip(&mut self) {
+        use Instruction::*;
+
+        let opcode = self.get_progmem(self.pc);
+        let prefetch = self.get_progmem(self.pc + 1);
+        match self.decode(opcode, prefetch) {
+            CALL { .. } | JMP { .. } | LDS { .. } | STS { .. } => {
+                self.pc += 2;
+                self.busy = 2
+            }
+            _ => {
+                self.pc += 1;
+                self.busy = 1
             }
         }
     }
 
+    // MCU CONTROL INSTRUCTIONS
+    fn sleep(&mut self) {
+        match &self.sleep_controller {
+            Some(controller) => self.sleeping = controller.borrow_mut().sleep(),
+            None => println!("[SLEEP]"), // Not implemented
+        }
+    }
+
+    #[allow(non_snake_case)]
+    fn des(&mut self, K: u8) {
+        // Data in R0..R7 and key in R8..R15, least significant byte first
+        let data = u64::from_le_bytes(self.regs[0..8].try_into().unwrap());
+        let key = u64::from_le_bytes(self.regs[8..16].try_into().unwrap());
+
+        let (data, key) = des::round(data, key, K, self.get_sreg_bit(BitSREG::H));
+
+        self.regs[0..8].copy_from_slice(&data.to_le_bytes());
+        self.regs[8..16].copy_from_slice(&key.to_le_bytes());
+    }
+
     // BIT MANIPULATION INSTRUCTIONS
     #[allow(non_snake_case)]
     fn asr(&mut self, d: u8) {
@@ -1002,12 +1083,17 @@ impl Core {
 
     #[allow(non_snake_case)]
     fn cbi(&mut self, A: u8, b: u8) {
+        if self.variant.classic() {
+            self.busy = 1;
+        }
+
         match A {
             0x3F => self.sreg &= !(1u8 << b),      // CPU.SREG
             0x3D => self.sp &= !(1u16 << b),       // CPU.SPL
             0x3E => self.sp &= !(1u16 << (b + 8)), // CPU.SPH
             _ => {
-                self.ds.borrow_mut().set_bit(usize::from(A), b, false);
+                let address = u32::from(A) + self.variant.io_offset();
+                self.ds.borrow_mut().set_bit(address as usize, b, false);
             }
         }
     }
@@ -1081,12 +1167,17 @@ impl Core {
 
     #[allow(non_snake_case)]
     fn sbi(&mut self, A: u8, b: u8) {
+        if self.variant.classic() {
+            self.busy = 1;
+        }
+
         match A {
             0x3F => self.sreg |= 1u8 << b,      // CPU.SREG
             0x3D => self.sp |= 1u16 << b,       // CPU.SPL
             0x3E => self.sp |= 1u16 << (b + 8), // CPU.SPH
             _ => {
-                self.ds.borrow_mut().set_bit(usize::from(A), b, true);
+                let address = u32::from(A) + self.variant.io_offset();
+                self.ds.borrow_mut().set_bit(address as usize, b, true);
             }
         }
     }
@@ -1103,8 +1194,6 @@ impl Core {
 
     #[allow(non_snake_case)]
     fn ld(&mut self, d: u8, pointer: PointerRegister, q: u8, dec: bool, inc: bool) {
-        self.busy = 2; // AVRxt, SRAM only
-
         let mut address = self.get_register_word(pointer as u8);
 
         if dec {
@@ -1112,14 +1201,15 @@ impl Core {
             self.set_register_word(pointer as u8, address)
         }
 
-        if (address as u32) + (q as u32) < 64 {
-            self.set_register(
-                d,
-                self.get_io_register(((address as u32) + (q as u32)) as u8),
-            );
-        } else {
-            self.set_register(d, self.get_data_space((address as u32) + (q as u32)));
-        }
+        let (value, latency) = self.get_data_space((address as u32) + (q as u32));
+        self.set_register(d, value);
+
+        // Single cycle from I/O on AVRxt, SRAM and flash add latency
+        self.busy = match self.variant {
+            CoreType::AVRxt => 0,
+            CoreType::AVRrc if !dec => 0,
+            _ => 1,
+        } + latency as u8;
 
         if inc {
             address = address.wrapping_add(1);
@@ -1129,8 +1219,6 @@ impl Core {
 
     #[allow(non_snake_case)]
     fn st(&mut self, r: u8, pointer: PointerRegister, q: u8, dec: bool, inc: bool) {
-        self.busy = 1;
-
         let mut address = self.get_register_word(pointer as u8);
 
         if dec {
@@ -1138,11 +1226,13 @@ impl Core {
             self.set_register_word(pointer as u8, address)
         }
 
-        if (address as u32) + (q as u32) < 64 {
-            self.set_io_register(((address as u32) + (q as u32)) as u8, self.get_register(r));
-        } else {
-            self.set_data_space((address as u32) + (q as u32), self.get_register(r));
-        }
+        let latency = self.set_data_space((address as u32) + (q as u32), self.get_register(r));
+
+        self.busy = match self.variant {
+            CoreType::AVRxt => 0,
+            CoreType::AVRxm | CoreType::AVRrc if !dec && (q == 0) => 0,
+            _ => 1,
+        } + latency as u8;
 
         if inc {
             address = address.wrapping_add(1);
@@ -1157,9 +1247,12 @@ impl Core {
 
     #[allow(non_snake_case)]
     fn lds(&mut self, d: u8, k: u16) {
-        self.busy = 2; // AVRxt, SRAM only
+        let (value, latency) = self.get_data_space(k as u32);
+        self.set_register(d, value);
 
-        self.set_register(d, self.get_data_space(k as u32));
+        // Two cycles plus the latency of the memory accessed, e.g. two cycles
+        // from I/O and three from SRAM or flash on AVRxt
+        self.busy = 1 + latency as u8;
     }
 
     #[allow(non_snake_case)]
@@ -1176,6 +1269,33 @@ impl Core {
         }
     }
 
+    #[allow(non_snake_case)]
+    fn elpm(&mut self, d: u8, inc: bool) {
+        self.busy = 2;
+
+        let mut address = (u32::from(self.rampz) << 16)
+            | u32::from(self.get_register_word(PointerRegister::Z as u8));
+
+        self.set_register(d, self.get_ps(address));
+
+        if inc {
+            address = address.wrapping_add(1);
+            self.set_register_word(PointerRegister::Z as u8, address as u16);
+            self.rampz = (address >> 16) as u8;
+        }
+    }
+
+    // XCH, LAS, LAC and LAT: (Z) <- f((Z), Rd), Rd <- (Z)
+    fn rmw(&mut self, d: u8, f: fn(u8, u8) -> u8) {
+        let address = u32::from(self.get_register_word(PointerRegister::Z as u8));
+        let (z, read_latency) = self.get_data_space(address);
+        let write_latency = self.set_data_space(address, f(z, self.get_register(d)));
+        self.set_register(d, z);
+
+        // Two cycles, plus the latency of the memory accessed
+        self.busy = 1 + (read_latency + write_latency) as u8;
+    }
+
     #[allow(non_snake_case)]
     fn mov(&mut self, d: u8, r: u8) {
         self.set_register(d, self.get_register(r))
@@ -1193,23 +1313,144 @@ impl Core {
 
     #[allow(non_snake_case)]
     fn pop(&mut self, d: u8) {
-        self.busy = 1; // 2 for AVRrc
-
         self.sp = self.sp.overflowing_add(1).0;
-        self.set_register(d, self.get_data_space(self.sp as u32));
+        let (value, latency) = self.get_data_space(self.sp as u32);
+        self.set_register(d, value);
+
+        // Two cycles from SRAM on AVRxt, SRAM adds latency
+        self.busy = match self.variant {
+            CoreType::AVRxt => 0,
+            _ => 1,
+        } + latency as u8;
     }
 
     #[allow(non_snake_case)]
     fn push(&mut self, r: u8) {
-        self.set_data_space(self.sp as u32, self.get_register(r));
+        let latency = self.set_data_space(self.sp as u32, self.get_register(r));
         self.sp = self.sp.overflowing_sub(1).0;
+
+        self.busy = u8::from(self.variant.classic()) + latency as u8;
     }
 
     #[allow(non_snake_case)]
     fn sts(&mut self, r: u8, k: u16) {
-        self.busy = 1; // SRAM only
+        let latency = self.set_data_space(k as u32, self.get_register(r));
+
+        self.busy = if self.variant == CoreType::AVRrc {
+            0
+        } else {
+            1
+        } + latency as u8;
+    }
 
-        self.set_data_space(k as u32, self.get_register(r));
+    fn decode(&self, opcode: u16, prefetch: u16) -> Instruction {
+        let op = match self.variant {
+            CoreType::AVRrc => Instruction::decode_reduced(opcode, prefetch),
+            _ => Instruction::decode(opcode, prefetch),
+        };
+        if self.implemented(&op) {
+            op
+        } else {
+            Instruction::UNDEF
+        }
+    }
+
+    // Instructions outside the subset of the core variant decode as illegal opcodes
+    fn implemented(&self, op: &Instruction) -> bool {
+        use Instruction::*;
+
+        match op {
+            // Only present on devices with flash beyond the reach of Z and PC
+            ELPM | ELPMZ { .. } | ELPMZinc { .. } => return self.extended,
+            EICALL | EIJMP => return self.pc_wide,
+            DES { .. } | XCH { .. } | LAS { .. } | LAC { .. } | LAT { .. } => {
+                return self.variant == CoreType::AVRxm
+            }
+            _ => {}
+        }
+
+        match self.variant {
+            CoreType::AVR => !matches!(
+                op,
+                CALL { .. }
+                    | JMP { .. }
+                    | MUL { .. }
+                    | MULS { .. }
+                    | MULSU { .. }
+                    | FMUL { .. }
+                    | FMULS { .. }
+                    | FMULSU { .. }
+                    | MOVW { .. }
+                    | LPMZ { .. }
+                    | LPMZinc { .. }
+                    | BREAK
+            ),
+            // Reduced core has registers R16 to R31 only
+            CoreType::AVRrc => match *op {
+                ADIW { .. }
+                | SBIW { .. }
+                | CALL { .. }
+                | JMP { .. }
+                | LDS { .. }
+                | STS { .. }
+                | LPM
+                | LPMZ { .. }
+                | LPMZinc { .. }
+                | MOVW { .. }
+                | MUL { .. }
+                | MULS { .. }
+                | MULSU { .. }
+                | FMUL { .. }
+                | FMULS { .. }
+                | FMULSU { .. } => false,
+                ADC { d, r }
+                | ADD { d, r }
+                | AND { d, r }
+                | CP { d, r }
+                | CPC { d, r }
+                | CPSE { d, r }
+                | EOR { d, r }
+                | MOV { d, r }
+                | OR { d, r }
+                | SBC { d, r }
+                | SUB { d, r } => (d >= 16) & (r >= 16),
+                LDDY { d, q } | LDDZ { d, q } => (d >= 16) & (q == 0),
+                STDY { r, q } | STDZ { r, q } => (r >= 16) & (q == 0),
+                ASR { d }
+                | BLD { d, .. }
+                | BST { d, .. }
+                | COM { d }
+                | DEC { d }
+                | IN { d, .. }
+                | INC { d }
+                | LDX { d }
+                | LDXinc { d }
+                | LDXdec { d }
+                | LDYinc { d }
+                | LDYdec { d }
+                | LDZinc { d }
+                | LDZdec { d }
+                | LSL { d }
+                | LSR { d }
+                | NEG { d }
+                | POP { d }
+                | PUSH { d }
+                | ROR { d }
+                | SWAP { d } => d >= 16,
+                OUT { r, .. }
+                | SBRC { r, .. }
+                | SBRS { r, .. }
+                | STX { r }
+                | STXdec { r }
+                | STXinc { r }
+                | STYdec { r }
+                | STYinc { r }
+                | STZdec { r }
+                | STZinc { r } => r >= 16,
+                _ => true,
+            },
+            _ => true,
+        }
     }
 
     pub fn tick(&mut self) -> bool {
@@ -1233,21 +1474,34 @@ impl Core {
             if self.get_sreg_bit(BitSREG::I) {
                 let vector = self.interrupt_handler.borrow_mut().service_pending();
                 if let Some(address) = vector {
-                    let mut ds = self.ds.borrow_mut();
-                    ds.write(usize::from(self.sp), (self.pc) as u8);
-                    self.sp -= 1;
-                    ds.write(usize::from(self.sp), ((self.pc) >> 8) as u8);
-                    self.sp -= 1;
-                    self.pc = address;
-                    self.busy = 4; // 2 cycles to to push PC + 3 cycles for jmp to vector
+                    if self.sleeping {
+                        self.sleeping = false;
+                        if let Some(controller) = &self.sleep_controller {
+                            controller.borrow_mut().wake();
+                        }
+                    }
+                    self.push_pc(self.pc);
+                    self.pc = u32::from(address);
+                    if matches!(self.variant, CoreType::AVRxm | CoreType::AVRxt) {
+                        self.busy = 4; // 2 cycles to to push PC + 3 cycles for jmp to vector
+                    } else {
+                        self.busy = 3; // 4 cycles to push PC, I bit cleared until reti
+                        self.set_sreg_bit(BitSREG::I, false);
+                    }
+                    self.busy += u8::from(self.pc_wide);
                     return true;
                 }
             }
         }
 
-        let opcode = self.get_progmem(self.pc as u32);
-        let prefetch = self.get_progmem((self.pc as u32) + 1);
-        let op = Instruction::decode(opcode, prefetch);
+        // Only an interrupt wakes the core
+        if self.sleeping {
+            return true;
+        }
+
+        let opcode = self.get_progmem(self.pc);
+        let prefetch = self.get_progmem(self.pc + 1);
+        let op = self.decode(opcode, prefetch);
 
         if self.debug {
             println!("[0x{:04X}] {:?}", self.pc << 1, op);
@@ -1255,8 +1509,8 @@ impl Core {
 
         // Most instructions are single cycle so do this first
         // Terminate if PC overflows to prevent program from restarting
-        if let Some(result) = self.pc.checked_add(1) {
-            self.pc = result;
+        if self.pc < self.pc_mask() {
+            self.pc += 1;
         } else {
             return false;
         }
@@ -1295,8 +1549,10 @@ impl Core {
             CPC { d, r } => self.cpc(d, r),
             CPI { d, K } => self.cpi(d, K),
             CPSE { d, r } => self.cpse(d, r),
-            ICALL => self.icall(),
-            IJMP => self.ijmp(),
+            EICALL => self.icall(true),
+            EIJMP => self.ijmp(true),
+            ICALL => self.icall(false),
+            IJMP => self.ijmp(false),
             JMP { k } => self.jmp(k),
             RCALL { k } => self.rcall(k),
             RET => self.ret(),
@@ -1322,6 +1578,13 @@ impl Core {
                 self.lds(d, k);
                 self.pc += 1
             }
+            ELPM => self.elpm(0, false),
+            ELPMZ { d } => self.elpm(d, false),
+            ELPMZinc { d } => self.elpm(d, true),
+            LAC { d } => self.rmw(d, |z, rd| !rd & z),
+            LAS { d } => self.rmw(d, |z, rd| rd | z),
+            LAT { d } => self.rmw(d, |z, rd| rd ^ z),
+            XCH { d } => self.rmw(d, |_, rd| rd),
             LPM => self.lpm(0, false),
             LPMZ { d } => self.lpm(d, false),
             LPMZinc { d } => self.lpm(d, true),
@@ -1343,6 +1606,8 @@ impl Core {
                 self.sts(r, address);
                 self.pc += 1
             }
+            LDS16 { d, k } => self.lds(d, u16::from(k)),
+            STS16 { r, k } => self.sts(r, u16::from(k)),
             // Bit and Bit-Test Instructions
             ASR { d } => self.asr(d),
             BCLR { s } => self.bclr(s),
@@ -1356,13 +1621,14 @@ impl Core {
             SBI { A, b } => self.sbi(A, b),
             SWAP { d } => self.swap(d),
             // MCU Control Instructions
+            DES { K } => self.des(K),
             BREAK => {
                 println!("[END] BREAK instruction encountered.");
                 return false;
             }
             NOP => {}
-            SLEEP => println!("[SLEEP]"), // Not implemented
-            WDR => println!("[WDR]"),     // Not implemented
+            SLEEP => self.sleep(),
+            WDR => println!("[WDR]"), // Not implemented
             // Undefined
             UNDEF => {
                 println!("[ERROR] Undefined opcode: {:b}", opcode)
@@ -1407,6 +1673,12 @@ enum Instruction {
     CPI { d: u8, K: u8 },
     CPSE { d: u8, r: u8 },
     DEC { d: u8 },
+    DES { K: u8 },
+    EICALL,
+    EIJMP,
+    ELPM,
+    ELPMZ { d: u8 },
+    ELPMZinc { d: u8 },
     EOR { d: u8, r: u8 },
     FMUL { d: u8, r: u8 },
     FMULS { d: u8, r: u8 },
@@ -1416,6 +1688,9 @@ enum Instruction {
     IN { d: u8, A: u8 },
     INC { d: u8 },
     JMP { k: u32 },
+    LAC { d: u8 },
+    LAS { d: u8 },
+    LAT { d: u8 },
     LDX { d: u8 },
     LDXinc { d: u8 },
     LDXdec { d: u8 },
@@ -1427,6 +1702,7 @@ enum Instruction {
     LDDZ { d: u8, q: u8 },
     LDI { d: u8, K: u8 },
     LDS { d: u8, k: u16 },
+    LDS16 { d: u8, k: u8 },
     LPM,
     LPMZ { d: u8 },
     LPMZinc { d: u8 },
@@ -1468,10 +1744,12 @@ enum Instruction {
     STDY { q: u8, r: u8 },
     STDZ { q: u8, r: u8 },
     STS { k: u16, r: u8 },
+    STS16 { k: u8, r: u8 },
     SUB { d: u8, r: u8 },
     SUBI { d: u8, K: u8 },
     SWAP { d: u8 },
     WDR,
+    XCH { d: u8 },
     UNDEF,
 }
 
@@ -1591,6 +1869,12 @@ impl Instruction {
                 r: r as u8,
             },
             "1001_010d_dddd_1010" => Instruction::DEC { d: d as u8 },
+            "1001_0100_KKKK_1011" => Instruction::DES { K: K as u8 },
+            "1001_0101_0001_1001" => Instruction::EICALL,
+            "1001_0100_0001_1001" => Instruction::EIJMP,
+            "1001_0101_1101_1000" => Instruction::ELPM,
+            "1001_000d_dddd_0110" => Instruction::ELPMZ { d: d as u8 },
+            "1001_000d_dddd_0111" => Instruction::ELPMZinc { d: d as u8 },
             "0010_01rd_dddd_rrrr" => Instruction::EOR {
                 d: d as u8,
                 r: r as u8,
@@ -1617,6 +1901,9 @@ impl Instruction {
             "1001_010k_kkkk_110k" => Instruction::JMP {
                 k: ((k as u32) << 16) | (prefetch as u32),
             },
+            "1001_001d_dddd_0110" => Instruction::LAC { d: d as u8 },
+            "1001_001d_dddd_0101" => Instruction::LAS { d: d as u8 },
+            "1001_001d_dddd_0111" => Instruction::LAT { d: d as u8 },
             "1001_000d_dddd_1100" => Instruction::LDX { d: d as u8 },
             "1001_000d_dddd_1101" => Instruction::LDXinc { d: d as u8 },
             "1001_000d_dddd_1110" => Instruction::LDXdec { d: d as u8 },
@@ -1767,10 +2054,305 @@ impl Instruction {
             },
             "1001_010d_dddd_0010" => Instruction::SWAP { d: d as u8 },
             "1001_0101_1010_1000" => Instruction::WDR,
+            "1001_001d_dddd_0100" => Instruction::XCH { d: d as u8 },
             _ => {
                 println!("[ERROR] Undefined opcode: {:b}", opcode);
                 Instruction::UNDEF
             }
         }
     }
+
+    // AVRrc replaces LDD/STD with 16-bit LDS/STS, addressing 0x40 to 0xBF
+    #[bitmatch]
+    fn decode_reduced(opcode: u16, prefetch: u16) -> Instruction {
+        let address = |k: u16| -> u8 {
+            let k = k as u8;
+            (!k & 0x10) << 3 | (k & 0x10) << 2 | (k & 0x60) >> 1 | (k & 0x0F)
+        };
+
+        #[bitmatch]
+        match opcode {
+            "1010_0kkk_dddd_kkkk" => Instruction::LDS16 {
+                d: d as u8 + 16,
+                k: address(k),
+            },
+            "1010_1kkk_rrrr_kkkk" => Instruction::STS16 {
+                k: address(k),
+                r: r as u8 + 16,
+            },
+            _ => Instruction::decode(opcode, prefetch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{Memory, MemoryMap};
+
+    // Single interrupt at the given vector, pending until serviced
+    struct TestInterrupt {
+        vector: u16,
+        pending: bool,
+        reti: usize,
+    }
+
+    impl InterruptHandler for TestInterrupt {
+        fn service_pending(&mut self) -> Option<u16> {
+            if self.pending {
+                self.pending = false;
+                Some(self.vector)
+            } else {
+                None
+            }
+        }
+
+        fn reti(&mut self) {
+            self.reti += 1;
+        }
+    }
+
+    // Core with registers from 0x0000, SRAM at 0x3800 and 32 KB of flash mapped at 0x8000
+    fn core(
+        variant: CoreType,
+        program: &[u16],
+        sram_latency: usize,
+    ) -> (Core, Rc<RefCell<TestInterrupt>>) {
+        core_with_flash(variant, program, sram_latency, 0x8000)
+    }
+
+    fn core_with_flash(
+        variant: CoreType,
+        program: &[u16],
+        sram_latency: usize,
+        flash_size: usize,
+    ) -> (Core, Rc<RefCell<TestInterrupt>>) {
+        let mut flash = Memory::new(flash_size, 0xFF, 1);
+        for (i, word) in program.iter().enumerate() {
+            flash.write(2 * i, *word as u8);
+            flash.write(2 * i + 1, (*word >> 8) as u8);
+        }
+        let flash: Rc<RefCell<dyn MemoryMapped>> = Rc::new(RefCell::new(flash));
+
+        let mut mm = MemoryMap::new();
+        mm.add(0x0000, Rc::new(RefCell::new(Memory::new(0x3800, 0x00, 0))));
+        mm.add(
+            0x3800,
+            Rc::new(RefCell::new(Memory::new(0x0800, 0x00, sram_latency))),
+        );
+        mm.add(0x8000, Rc::clone(&flash));
+
+        let interrupt = Rc::new(RefCell::new(TestInterrupt {
+            vector: 0x0010,
+            pending: false,
+            reti: 0,
+        }));
+        let core = Core::new(
+            variant,
+            Rc::new(RefCell::new(mm)),
+            flash,
+            interrupt.clone(),
+            None,
+            0x3FFF,
+        );
+        (core, interrupt)
+    }
+
+    // Executes one instruction, returning the number of cycles taken
+    fn step(core: &mut Core) -> usize {
+        let mut cycles = 1;
+        core.tick();
+        while core.busy > 0 {
+            core.tick();
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn reduced_lds_sts_address() {
+        // 7-bit k field and the data space address it selects
+        let cases = [
+            (0x00, 0x80),
+            (0x0F, 0x8F),
+            (0x10, 0x40),
+            (0x1F, 0x4F),
+            (0x20, 0x90),
+            (0x40, 0xA0),
+            (0x6F, 0xBF),
+            (0x7F, 0x7F),
+        ];
+        for (k, address) in cases {
+            let field = ((k & 0x70) << 4) | (k & 0x0F);
+            assert!(
+                matches!(
+                    Instruction::decode_reduced(0xA000 | field | (0x5 << 4), 0),
+                    Instruction::LDS16 { d: 21, k: a } if u16::from(a) == address
+                ),
+                "LDS16 k = 0x{:02X}",
+                k
+            );
+            assert!(
+                matches!(
+                    Instruction::decode_reduced(0xA800 | field | (0xA << 4), 0),
+                    Instruction::STS16 { r: 26, k: a } if u16::from(a) == address
+                ),
+                "STS16 k = 0x{:02X}",
+                k
+            );
+        }
+    }
+
+    #[test]
+    fn reduced_lds_sts_access() {
+        // STS 0x40, R16; LDS R17, 0x40; LDS R18, 0xBF
+        let (mut core, _) = core(CoreType::AVRrc, &[0xA900, 0xA110, 0xA62F], 1);
+        core.set_register(16, 0x5A);
+        core.ds.borrow_mut().write(0xBF, 0xA5);
+        for _ in 0..3 {
+            step(&mut core);
+        }
+        assert_eq!(core.ds.borrow_mut().read(0x40).0, 0x5A);
+        assert_eq!(core.get_register(17), 0x5A);
+        assert_eq!(core.get_register(18), 0xA5);
+    }
+
+    #[test]
+    fn reti_sets_i_on_classic_cores() {
+        // SEI; NOP; NOP, with RETI at the vector
+        let mut program = vec![0x9478, 0x0000, 0x0000];
+        program.resize(0x10, 0x0000);
+        program.push(0x9518);
+
+        for (variant, classic) in [
+            (CoreType::AVRe, true),
+            (CoreType::AVReplus, true),
+            (CoreType::AVRxt, false),
+            (CoreType::AVRrc, true),
+        ] {
+            let (mut core, interrupt) = core(variant, &program, 0);
+            step(&mut core); // SEI
+            interrupt.borrow_mut().pending = true;
+
+            step(&mut core); // Vector to 0x0010
+            assert_eq!(core.pc, 0x0010);
+            assert_eq!(core.get_sreg_bit(BitSREG::I), !classic);
+
+            step(&mut core); // RETI
+            assert_eq!(core.pc, 0x0001);
+            assert!(core.get_sreg_bit(BitSREG::I));
+            assert_eq!(interrupt.borrow().reti, 1);
+        }
+    }
+
+    #[test]
+    fn extended_addressing_by_flash_size() {
+        // Flash size, RAMPZ and ELPM present, EIND and EICALL/EIJMP present
+        for (flash_size, rampz, eind) in [
+            (32 * 1024, false, false),
+            (128 * 1024, true, false),
+            (256 * 1024, true, true),
+        ] {
+            let (mut core, _) = core_with_flash(CoreType::AVReplus, &[], 0, flash_size);
+            for (opcode, present) in [
+                (0x95D8, rampz), // ELPM
+                (0x9006, rampz), // ELPM R0, Z
+                (0x9519, eind),  // EICALL
+                (0x9419, eind),  // EIJMP
+            ] {
+                assert_eq!(
+                    !matches!(core.decode(opcode, 0), Instruction::UNDEF),
+                    present,
+                    "opcode 0x{:04X} with {} KB of flash",
+                    opcode,
+                    flash_size / 1024
+                );
+            }
+
+            // Otherwise I/O 0x3B and 0x3C are ordinary registers, e.g. PCIFR and EIFR
+            core.set_data_space(0x5B, 0x01);
+            core.set_data_space(0x5C, 0x02);
+            assert_eq!(core.rampz == 0x01, rampz);
+            assert_eq!(core.eind == 0x02, eind);
+            assert_eq!(core.ds.borrow_mut().read(0x5B).0 == 0x01, !rampz);
+            assert_eq!(core.ds.borrow_mut().read(0x5C).0 == 0x02, !eind);
+        }
+    }
+
+    #[test]
+    fn data_memory_cycles() {
+        const IO: u16 = 0x0050;
+        const SRAM: u16 = 0x3900;
+        const FLASH: u16 = 0x8100;
+
+        const LD_X: &[u16] = &[0x910C];
+        const LD_X_INC: &[u16] = &[0x910D];
+        const LD_X_DEC: &[u16] = &[0x910E];
+        const ST_X: &[u16] = &[0x930C];
+        const ST_X_DEC: &[u16] = &[0x930E];
+        const LDS_IO: &[u16] = &[0x9100, IO];
+        const LDS_SRAM: &[u16] = &[0x9100, SRAM];
+        const LDS_FLASH: &[u16] = &[0x9100, FLASH];
+        const STS_IO: &[u16] = &[0x9300, IO];
+        const STS_SRAM: &[u16] = &[0x9300, SRAM];
+        const PUSH: &[u16] = &[0x930F];
+        const POP: &[u16] = &[0x910F];
+        const XCH: &[u16] = &[0x9304];
+        const LAS: &[u16] = &[0x9305];
+
+        // Core, SRAM latency, instruction, X, Z and SP, cycles
+        let cases = [
+            (CoreType::AVRxt, 1, LD_X, IO, 1),
+            (CoreType::AVRxt, 1, LD_X, SRAM, 2),
+            (CoreType::AVRxt, 1, LD_X, FLASH, 2),
+            (CoreType::AVRxt, 1, LD_X_INC, SRAM, 2),
+            (CoreType::AVRxt, 1, LD_X_DEC, SRAM + 1, 2),
+            (CoreType::AVRxt, 1, ST_X, IO, 1),
+            (CoreType::AVRxt, 1, ST_X, SRAM, 1),
+            (CoreType::AVRxt, 1, LDS_IO, 0, 2),
+            (CoreType::AVRxt, 1, LDS_SRAM, 0, 3),
+            (CoreType::AVRxt, 1, LDS_FLASH, 0, 3),
+            (CoreType::AVRxt, 1, STS_IO, 0, 2),
+            (CoreType::AVRxt, 1, STS_SRAM, 0, 2),
+            (CoreType::AVRxt, 1, PUSH, SRAM, 1),
+            (CoreType::AVRxt, 1, POP, SRAM - 1, 2),
+            (CoreType::AVRe, 0, LD_X, IO, 2),
+            (CoreType::AVRe, 0, LD_X, SRAM, 2),
+            (CoreType::AVRe, 0, LD_X_INC, SRAM, 2),
+            (CoreType::AVRe, 0, LD_X_DEC, SRAM + 1, 2),
+            (CoreType::AVRe, 0, ST_X, IO, 2),
+            (CoreType::AVRe, 0, ST_X, SRAM, 2),
+            (CoreType::AVRe, 0, LDS_IO, 0, 2),
+            (CoreType::AVRe, 0, LDS_SRAM, 0, 2),
+            (CoreType::AVRe, 0, STS_SRAM, 0, 2),
+            (CoreType::AVRe, 0, PUSH, SRAM, 2),
+            (CoreType::AVRe, 0, POP, SRAM - 1, 2),
+            (CoreType::AVRrc, 1, LD_X, IO, 1),
+            (CoreType::AVRrc, 1, LD_X, SRAM, 2),
+            (CoreType::AVRrc, 1, LD_X_DEC, IO + 1, 2),
+            (CoreType::AVRrc, 1, LD_X_DEC, SRAM + 1, 3),
+            (CoreType::AVRrc, 1, ST_X, SRAM, 1),
+            (CoreType::AVRrc, 1, ST_X_DEC, SRAM + 1, 2),
+            (CoreType::AVRrc, 1, PUSH, SRAM, 1),
+            (CoreType::AVRrc, 1, POP, SRAM - 1, 3),
+            (CoreType::AVRxm, 0, XCH, SRAM, 2),
+            (CoreType::AVRxm, 0, LAS, SRAM, 2),
+            (CoreType::AVRxm, 1, XCH, SRAM, 3),
+            (CoreType::AVRxm, 1, LAS, SRAM, 3),
+        ];
+        for (variant, sram_latency, program, pointer, cycles) in cases {
+            let (mut core, _) = core(variant, program, sram_latency);
+            core.set_register_word(PointerRegister::X as u8, pointer);
+            core.set_register_word(PointerRegister::Z as u8, pointer);
+            core.sp = pointer;
+            assert_eq!(
+                step(&mut core),
+                cycles,
+                "opcode 0x{:04X} at 0x{:04X} with SRAM latency {}",
+                program[0],
+                pointer,
+                sram_latency
+            );
+        }
+    }
 }
@@ -1,17 +1,21 @@
 use super::cores::Core;
-use super::cores::CoreType;
 use super::memory::Memory;
 use super::memory::MemoryMap;
 use super::memory::MemoryMapped;
 
-use crate::cores::InterruptHandler;
+use crate::cores::{InterruptHandler, SleepController};
 use crate::hardware::Hardware;
-use crate::peripherals::adc::Adc;
+use crate::peripherals::adc::{Adc, ADC_TEMPSENSE0, ADC_TEMPSENSE1};
 use crate::peripherals::clkctrl::Clkctrl;
+use crate::peripherals::clkpr::Clkpr;
 use crate::peripherals::cpu::Cpu;
 use crate::peripherals::cpuint::Cpuint;
-use crate::peripherals::port::{Port, VirtualPort};
+use crate::peripherals::evsys::{Evout, Evsys};
+use crate::peripherals::exint::{Exint, ExintFlags};
+use crate::peripherals::port::{ClassicPort, Port, VirtualPort};
 use crate::peripherals::portmux::Portmux;
+use crate::peripherals::slpctrl::{SleepMode, Slpctrl};
+use crate::peripherals::smcr::Smcr;
 use crate::peripherals::spi::Spi;
 use crate::peripherals::stdio::Stdio;
 use crate::peripherals::tca::Tca;
@@ -20,8 +24,11 @@ use crate::peripherals::usart::Usart;
 use crate::peripherals::ClockSource;
 use crate::peripherals::Clocked;
 use crate::peripherals::InterruptSource;
+use crate::peripherals::PinMux;
+use crate::peripherals::{EventGenerator, EventUser};
 
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
@@ -30,41 +37,120 @@ use std::rc::Rc;
 use ihex::Reader;
 use ihex::Record;
 
+pub mod descriptions;
+
+use descriptions::{DeviceDescription, Family, Peripheral};
+
+const PORTS: [&str; 4] = ["PORTA", "PORTB", "PORTC", "PORTD"];
+
+#[derive(Clone, Copy)]
 pub enum DeviceType {
+    ATtiny3227,
+    ATtiny3226,
+    ATtiny1627,
     ATtiny1626,
+    ATtiny826,
+    ATtiny3216,
+    ATtiny1614,
+    ATmega328P,
+}
+
+impl DeviceType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "attiny3227" => Some(DeviceType::ATtiny3227),
+            "attiny3226" => Some(DeviceType::ATtiny3226),
+            "attiny1627" => Some(DeviceType::ATtiny1627),
+            "attiny1626" => Some(DeviceType::ATtiny1626),
+            "attiny826" => Some(DeviceType::ATtiny826),
+            "attiny3216" => Some(DeviceType::ATtiny3216),
+            "attiny1614" => Some(DeviceType::ATtiny1614),
+            "atmega328p" => Some(DeviceType::ATmega328P),
+            _ => None,
+        }
+    }
+
+    pub fn description(&self) -> &'static DeviceDescription {
+        match self {
+            DeviceType::ATtiny3227 => &descriptions::ATTINY3227,
+            DeviceType::ATtiny3226 => &descriptions::ATTINY3226,
+            DeviceType::ATtiny1627 => &descriptions::ATTINY1627,
+            DeviceType::ATtiny1626 => &descriptions::ATTINY1626,
+            DeviceType::ATtiny826 => &descriptions::ATTINY826,
+            DeviceType::ATtiny3216 => &descriptions::ATTINY3216,
+            DeviceType::ATtiny1614 => &descriptions::ATTINY1614,
+            DeviceType::ATmega328P => &descriptions::ATMEGA328P,
+        }
+    }
 }
 
 #[allow(non_snake_case)]
 pub struct Device {
     pub core: Core,
     pub flash: Rc<RefCell<dyn MemoryMapped>>,
+    #[allow(dead_code)]
     pub sram: Rc<RefCell<dyn MemoryMapped>>,
     pub mm: Rc<RefCell<dyn MemoryMapped>>,
     pub ports: Vec<Rc<RefCell<Port>>>,
+    pub pins: Vec<u8>, // bonded pins on each port
     pub stdio: Rc<RefCell<Stdio>>,
     clock_source: Rc<RefCell<dyn ClockSource>>,
     clocked: Vec<Rc<RefCell<dyn Clocked>>>,
+    running: Vec<bool>, // peripherals still clocked in the current sleep mode
+    slpctrl: Rc<RefCell<dyn SleepController>>,
+    sleep_mode: Option<SleepMode>,
     RAMEND: u16,
 }
 
 impl Device {
     pub fn new(dt: DeviceType) -> Self {
-        match dt {
-            DeviceType::ATtiny1626 => {
-                // Constants
-                const RAMEND: u16 = 0x3FFF;
+        let desc = dt.description();
 
+        // Memories, with an extra cycle for data reads from SRAM on AVRxt and
+        // from anything behind the NVM controller
+        let sram_latency = match desc.family {
+            Family::TinyAvr => 1,
+            Family::Classic => 0,
+        };
+        let flash: Rc<RefCell<dyn MemoryMapped>> =
+            Rc::new(RefCell::new(Memory::new(desc.flash_size, 0xFF, 1)));
+        let sram: Rc<RefCell<dyn MemoryMapped>> = Rc::new(RefCell::new(Memory::new(
+            desc.sram_size,
+            0x00,
+            sram_latency,
+        )));
+
+        // Ports
+        let ports: Vec<Rc<RefCell<Port>>> = PORTS[..desc.pins.len()]
+            .iter()
+            .map(|name| Rc::new(RefCell::new(Port::new(name.to_string()))))
+            .collect();
+
+        let stdio = Rc::new(RefCell::new(Stdio::new(
+            "STDIO".to_string(),
+            "stdout.txt".to_string(),
+        )));
+
+        let cpuint = Rc::new(RefCell::new(Cpuint::new(desc.vectors, desc.flash_size)));
+
+        let mut map: Vec<(usize, Rc<RefCell<dyn MemoryMapped>>)> = Vec::new();
+        let mut clocked: Vec<Rc<RefCell<dyn Clocked>>> = Vec::new();
+
+        // System peripherals and memory layout
+        #[allow(clippy::type_complexity)]
+        let (clock_source, ramend, slpctrl): (
+            Rc<RefCell<dyn ClockSource>>,
+            u16,
+            Rc<RefCell<dyn SleepController>>,
+        ) = match desc.family {
+            Family::TinyAvr => {
                 // Clocking
                 let clkctrl = Rc::new(RefCell::new(Clkctrl::new()));
 
                 // Cpu
                 let cpu = Rc::new(RefCell::new(Cpu::new(vec![clkctrl.clone()])));
+                clocked.push(cpu.clone());
 
-                // Memories
-                let flash: Rc<RefCell<dyn MemoryMapped>> =
-                    Rc::new(RefCell::new(Memory::new(16 * 1024, 0xFF, 0)));
-                let sram: Rc<RefCell<dyn MemoryMapped>> =
-                    Rc::new(RefCell::new(Memory::new(2 * 1024, 0x00, 0)));
                 let gpio: Rc<RefCell<dyn MemoryMapped>> =
                     Rc::new(RefCell::new(Memory::new(4, 0x00, 0)));
 
@@ -73,312 +159,358 @@ impl Device {
                     Rc::new(RefCell::new(Memory::new_rom(vec![0x00, 0x04], 0))); // Rev E (0x04?) is inital release
                 let fuse: Rc<RefCell<dyn MemoryMapped>> =
                     Rc::new(RefCell::new(Memory::new_rom(vec![0x00, 0x00, 0x7E], 0)));
+                let sigrow: Rc<RefCell<dyn MemoryMapped>> = {
+                    let mut sigrow = vec![0x00; 0x20];
+                    sigrow[0x00..0x03].copy_from_slice(&desc.signature); // DEVICEID
+                    sigrow[0x04..0x06].copy_from_slice(&ADC_TEMPSENSE0.to_le_bytes());
+                    sigrow[0x06..0x08].copy_from_slice(&ADC_TEMPSENSE1.to_le_bytes());
+                    Rc::new(RefCell::new(Memory::new_rom(sigrow, 0)))
+                };
 
                 // Placeholder
-                let eeprom: Rc<RefCell<dyn MemoryMapped>> =
-                    Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 256], 0))); // Should this read 0xFF?
+                let eeprom: Rc<RefCell<dyn MemoryMapped>> = Rc::new(RefCell::new(Memory::new_rom(
+                    vec![0x00; desc.eeprom_size],
+                    1,
+                ))); // Should this read 0xFF?
                 let userrow: Rc<RefCell<dyn MemoryMapped>> =
-                    Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 0x80], 0))); // Should this read 0xFF?
-
-                // Ports
-                let porta = Rc::new(RefCell::new(Port::new("PORTA".to_string())));
-                let portb = Rc::new(RefCell::new(Port::new("PORTB".to_string())));
-                let portc = Rc::new(RefCell::new(Port::new("PORTC".to_string())));
-
-                let vporta = Rc::new(RefCell::new(VirtualPort {
-                    port: Rc::clone(&porta),
-                }));
-                let vportb = Rc::new(RefCell::new(VirtualPort {
-                    port: Rc::clone(&portb),
-                }));
-                let vportc = Rc::new(RefCell::new(VirtualPort {
-                    port: Rc::clone(&portc),
-                }));
-
-                let portmux = Rc::new(RefCell::new(Portmux::new("PORTMUX".to_string())));
-
-                let ports = vec![Rc::clone(&porta), Rc::clone(&portb), Rc::clone(&portc)];
-
-                let spi0 = Rc::new(RefCell::new(Spi::new(
-                    "SPI0".to_string(),
-                    Rc::clone(&porta),
-                    [1, 2, 3, 4],
-                    Rc::clone(&portc),
-                    [2, 1, 0, 3],
-                )));
-                spi0.borrow_mut().mux_alt = true;
-
-                let usart0 = Rc::new(RefCell::new(Usart::new(
-                    "USART0".to_string(),
-                    Rc::clone(&portb),
-                    [3, 2, 1, 0],
-                    Rc::clone(&porta),
-                    [2, 1, 3, 4],
-                )));
-
-                let usart1 = Rc::new(RefCell::new(Usart::new(
-                    "USART1".to_string(),
-                    Rc::clone(&porta),
-                    [2, 1, 3, 4],
-                    Rc::clone(&portc),
-                    [1, 2, 0, 3],
-                )));
-
-                let tca0 = Rc::new(RefCell::new(Tca::new(
-                    "TCA0".to_string(),
-                    Rc::clone(&portb),
-                    [0, 1, 3],
-                    [3, 4, 5],
-                )));
-                let tcb0 = Rc::new(RefCell::new(Tcb::new("TCB0".to_string())));
-                let tcb1 = Rc::new(RefCell::new(Tcb::new("TCB1".to_string())));
-
-                let adc0 = Rc::new(RefCell::new(Adc::new(
-                    "ADC0".to_string(),
-                    [Rc::clone(&porta), Rc::clone(&portb), Rc::clone(&portc)],
-                    [
-                        (0, 1),
-                        (0, 2),
-                        (0, 3),
-                        (0, 4),
-                        (0, 5),
-                        (0, 6),
-                        (0, 7),
-                        (1, 5),
-                        (1, 4),
-                        (1, 1),
-                        (1, 0),
-                        (2, 0),
-                        (2, 1),
-                        (2, 2),
-                        (2, 3),
-                    ],
-                )));
-
-                let clocked = vec![
-                    cpu.clone() as Rc<RefCell<dyn Clocked>>,
-                    spi0.clone() as Rc<RefCell<dyn Clocked>>,
-                    tca0.clone() as Rc<RefCell<dyn Clocked>>,
-                    tcb0.clone() as Rc<RefCell<dyn Clocked>>,
-                    tcb1.clone() as Rc<RefCell<dyn Clocked>>,
-                    adc0.clone() as Rc<RefCell<dyn Clocked>>,
-                    usart0.clone() as Rc<RefCell<dyn Clocked>>,
-                    usart1.clone() as Rc<RefCell<dyn Clocked>>,
-                ];
-
-                let stdio = Rc::new(RefCell::new(Stdio::new(
-                    "STDIO".to_string(),
-                    "stdout.txt".to_string(),
-                )));
-
-                let cpuint = Rc::new(RefCell::new(Cpuint::new()));
-                cpuint.borrow_mut().add_source(
-                    8,
-                    tca0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x01,
-                ); // OVF
-                cpuint.borrow_mut().add_source(
-                    10,
-                    tca0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x10,
-                ); // CMP0
-                cpuint.borrow_mut().add_source(
-                    11,
-                    tca0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x20,
-                ); // CMP1
-                cpuint.borrow_mut().add_source(
-                    12,
-                    tca0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x40,
-                ); // CMP2
-                cpuint.borrow_mut().add_source(
-                    13,
-                    tcb0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x03,
-                );
-                cpuint.borrow_mut().add_source(
-                    16,
-                    spi0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0xF1,
-                );
-                cpuint.borrow_mut().add_source(
-                    17,
-                    usart0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x80,
-                ); // RCX
-                cpuint.borrow_mut().add_source(
-                    18,
-                    usart0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x20,
-                ); // DRE
-                cpuint.borrow_mut().add_source(
-                    19,
-                    usart0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x40,
-                ); // TXC
-                cpuint.borrow_mut().add_source(
-                    22,
-                    adc0.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x01,
-                ); // RESRDY
-                cpuint.borrow_mut().add_source(
-                    25,
-                    tcb1.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x03,
-                );
-                cpuint.borrow_mut().add_source(
-                    26,
-                    usart1.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x80,
-                ); // RCX
-                cpuint.borrow_mut().add_source(
-                    27,
-                    usart1.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x20,
-                ); // DRE
-                cpuint.borrow_mut().add_source(
-                    28,
-                    usart1.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0x40,
-                ); // TXC
-                cpuint.borrow_mut().add_source(
-                    6,
-                    porta.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0xFF,
-                ); // PORTA
-                cpuint.borrow_mut().add_source(
-                    7,
-                    portb.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0xFF,
-                ); // PORTB
-                cpuint.borrow_mut().add_source(
-                    24,
-                    portc.clone() as Rc<RefCell<dyn InterruptSource>>,
-                    0xFF,
-                ); // PORTC
+                    Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 0x80], 1))); // Should this read 0xFF?
+
+                // Sleep
+                let sleep = Rc::new(RefCell::new(Slpctrl::new()));
 
                 // Not implemented
-                let slpctrl: Rc<RefCell<dyn MemoryMapped>> =
-                    Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 0x01], 0)));
                 let bod: Rc<RefCell<dyn MemoryMapped>> =
                     Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 0x0C], 0)));
-                let twi: Rc<RefCell<dyn MemoryMapped>> =
-                    Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 0x0F], 0)));
                 let crcscan: Rc<RefCell<dyn MemoryMapped>> =
                     Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 0x03], 0)));
-                let ac0: Rc<RefCell<dyn MemoryMapped>> =
-                    Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 0x08], 0)));
                 let nvmctrl: Rc<RefCell<dyn MemoryMapped>> =
                     Rc::new(RefCell::new(Memory::new_rom(vec![0x00; 0x09], 0)));
 
-                let mut mm = MemoryMap::new();
-
                 // PERIPHERAL MAP
-                //  0: [0x0000] VPORTA
-                mm.add(0x0000, vporta.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                //  1: [0x0004] VPORTB
-                mm.add(0x0004, vportb.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                //  2: [0x0008] VPORTC
-                mm.add(0x0008, vportc.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                //  3: [0x001C] GPIO
-                mm.add(0x001C, Rc::clone(&gpio));
-                //  4: [0x0030] CPU
-                mm.add(0x0030, cpu.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // xx: [0x0040] RSTCTRL
-                //  5: [0x0050] SLPCTRL
-                mm.add(0x0050, Rc::clone(&slpctrl));
-                //  6: [0x0060] CLKCTRL
-                mm.add(0x0060, clkctrl.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                //  7: [0x0080] BOD
-                mm.add(0x0080, Rc::clone(&bod));
-                // xx: [0x00A0] VREF
-                // xx: [0x0100] WDT
-                //  8: [0x0110] CPUINT
-                mm.add(0x0110, cpuint.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                //  9: [0x0120] CRCSCAN (not implemented)
-                mm.add(0x0120, Rc::clone(&crcscan));
-                // xx: [0x0140] RTC
-                // xx: [0x0180] EVSYS
-                // xx: [0x01C0] CCL
-                // 10: [0x0400] PORTA
-                mm.add(0x0400, porta.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 11: [0x0420] PORTB
-                mm.add(0x0420, portb.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 12: [0x0440] PORTC
-                mm.add(0x0440, portc.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 13: [0x05E0] PORTMUX
-                mm.add(0x05E0, portmux.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 14: [0x0600] ADC0
-                mm.add(0x0600, adc0.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 15: [0x0680] AC0
-                mm.add(0x0680, Rc::clone(&ac0));
-                // 16: [0x0800] USART0
-                mm.add(0x0800, usart0.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 17: [0x0820] USART1
-                mm.add(0x0820, usart1.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 18: [0x08A0] TWI0
-                mm.add(0x08A0, Rc::clone(&twi));
-                // 19: [0x08C0] SPI0
-                mm.add(0x08C0, spi0.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 20: [0x0A00] TCA0
-                mm.add(0x0A00, tca0.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 21: [0x0A80] TCB0
-                mm.add(0x0A80, tcb0.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 22: [0x0A90] TCB1
-                mm.add(0x0A90, tcb1.clone() as Rc<RefCell<dyn MemoryMapped>>);
-                // 23: [0x0F00] SYSCFG
-                mm.add(0x0F00, Rc::clone(&syscfg));
-                // 24: [0x1000] NVMCTRL
-                mm.add(0x1000, Rc::clone(&nvmctrl));
+                for (i, port) in ports.iter().enumerate() {
+                    let vport = Rc::new(RefCell::new(VirtualPort {
+                        port: Rc::clone(port),
+                    }));
+                    map.push((4 * i, vport)); // VPORTx
+                    map.push((0x0400 + 0x20 * i, port.clone())); // PORTx
+                }
+                map.push((0x001C, Rc::clone(&gpio))); // GPIO
+                map.push((0x0030, cpu.clone())); // CPU
+                map.push((0x0050, sleep.clone())); // SLPCTRL
+                map.push((0x0060, clkctrl.clone())); // CLKCTRL
+                map.push((0x0080, Rc::clone(&bod))); // BOD
+                map.push((0x0110, cpuint.clone())); // CPUINT
+                map.push((0x0120, Rc::clone(&crcscan))); // CRCSCAN (not implemented)
+                map.push((0x0F00, Rc::clone(&syscfg))); // SYSCFG
+                map.push((0x1000, Rc::clone(&nvmctrl))); // NVMCTRL
 
                 // SYSTEM MEMORY MAP
-                // xx: [0x1100] SIGROW
-                // xx: [0x1200-127F] RESERVED
-                // 25: [0x1280] FUSE
-                mm.add(0x1280, Rc::clone(&fuse));
-                // xx: [0x128A] LOCKBIT
-                // 26: [0x1300] USERROW
-                mm.add(0x1300, Rc::clone(&userrow));
-                // xx: [0x1380-13FF] RESERVED
-                // 27: [0x1400] EEPROM
-                mm.add(0x1400, Rc::clone(&eeprom));
-
-                // [0x1500-0x37FF] RESERVED
-
-                // 28: [0x1500]
-                mm.add(0x1500, stdio.clone() as Rc<RefCell<dyn MemoryMapped>>);
-
-                // 29: [0x3800] SRAM
-                mm.add(0x3800, Rc::clone(&sram));
-
-                // [0x4000-0x7FFF] RESERVED
-
-                // 30: [0x8000] FLASH
-                mm.add(0x8000, Rc::clone(&flash));
-
-                // [0xC000-0xFFFF] RESERVED
-
-                let mm: Rc<RefCell<dyn MemoryMapped>> = Rc::new(RefCell::new(mm));
-
-                Device {
-                    core: Core::new(
-                        CoreType::AVRxt,
-                        Rc::clone(&mm),
-                        Rc::clone(&flash),
-                        cpuint.clone() as Rc<RefCell<dyn InterruptHandler>>,
-                        RAMEND,
-                    ),
-                    flash,
-                    sram,
-                    mm,
-                    ports,
-                    clock_source: clkctrl.clone() as Rc<RefCell<dyn ClockSource>>,
-                    clocked,
-                    stdio,
-                    RAMEND,
+                map.push((0x1100, Rc::clone(&sigrow))); // SIGROW
+                map.push((0x1280, Rc::clone(&fuse))); // FUSE
+                map.push((0x1300, Rc::clone(&userrow))); // USERROW
+                map.push((0x1400, Rc::clone(&eeprom))); // EEPROM
+                map.push((0x8000, Rc::clone(&flash))); // FLASH
+
+                (clkctrl, 0x3FFF, sleep)
+            }
+            Family::Classic => {
+                // Clocking
+                let clkpr = Rc::new(RefCell::new(Clkpr::new()));
+                clocked.push(clkpr.clone());
+
+                // Registers R0..R31, SREG and SP are held by the core
+                for (i, port) in ports.iter().enumerate() {
+                    if desc.pins[i] > 0 {
+                        let cport = Rc::new(RefCell::new(ClassicPort {
+                            port: Rc::clone(port),
+                        }));
+                        map.push((0x0020 + 3 * i, cport)); // PINx, DDRx, PORTx
+                    }
+                }
+                // Sleep
+                let smcr = Rc::new(RefCell::new(Smcr::new()));
+                map.push((0x0053, smcr.clone())); // SMCR
+                map.push((0x0061, clkpr.clone())); // CLKPR
+
+                (clkpr, (0x00FF + desc.sram_size) as u16, smcr)
+            }
+        };
+
+        // Peripheral instances, looked up by name when wiring interrupts and events
+        let mut registers: HashMap<&str, Rc<RefCell<dyn MemoryMapped>>> = HashMap::new();
+        let mut sources: HashMap<&str, Rc<RefCell<dyn InterruptSource>>> = HashMap::new();
+        let mut generators: HashMap<&str, Rc<RefCell<dyn EventGenerator>>> = HashMap::new();
+        let mut users: HashMap<&str, Rc<RefCell<dyn EventUser>>> = HashMap::new();
+        #[allow(clippy::type_complexity)]
+        let mut routes: Vec<((usize, u8), Rc<RefCell<dyn PinMux>>)> = Vec::new();
+        let mut tcas: HashMap<&str, Rc<RefCell<Tca>>> = HashMap::new();
+        let mut unsupported = Vec::new();
+        let mut portmux = None;
+        let mut evsys = None;
+
+        for (name, port) in PORTS.into_iter().zip(&ports) {
+            sources.insert(name, port.clone());
+            generators.insert(name, port.clone());
+        }
+
+        for peripheral in desc.peripherals {
+            match *peripheral {
+                Peripheral::Portmux { address } => {
+                    let p = Rc::new(RefCell::new(Portmux::new("PORTMUX".to_string())));
+                    map.push((address, p.clone()));
+                    portmux = Some(p);
+                }
+                Peripheral::Evsys { address } => {
+                    let p = Rc::new(RefCell::new(Evsys::new("EVSYS".to_string())));
+                    map.push((address, p.clone()));
+                    evsys = Some(p);
+                }
+                Peripheral::Evout {
+                    name,
+                    pin,
+                    pin_alt,
+                    route,
+                } => {
+                    let p = Rc::new(RefCell::new(Evout::new(
+                        Rc::clone(&ports[pin.0]),
+                        pin.1,
+                        Rc::clone(&ports[pin_alt.0]),
+                        pin_alt.1,
+                    )));
+                    users.insert(name, p.clone());
+                    routes.push((route, p));
+                }
+                Peripheral::Spi {
+                    name,
+                    address,
+                    pins,
+                    pins_alt,
+                    route,
+                } => {
+                    let p = Rc::new(RefCell::new(Spi::new(
+                        name.to_string(),
+                        Rc::clone(&ports[pins.0]),
+                        pins.1,
+                        Rc::clone(&ports[pins_alt.0]),
+                        pins_alt.1,
+                    )));
+                    map.push((address, p.clone()));
+                    sources.insert(name, p.clone());
+                    routes.push((route, p.clone()));
+                    clocked.push(p);
+                }
+                Peripheral::Usart {
+                    name,
+                    address,
+                    pins,
+                    pins_alt,
+                    route,
+                } => {
+                    let p = Rc::new(RefCell::new(Usart::new(
+                        name.to_string(),
+                        Rc::clone(&ports[pins.0]),
+                        pins.1,
+                        Rc::clone(&ports[pins_alt.0]),
+                        pins_alt.1,
+                    )));
+                    map.push((address, p.clone()));
+                    sources.insert(name, p.clone());
+                    routes.push((route, p.clone()));
+                    clocked.push(p);
+                }
+                Peripheral::Tca {
+                    name,
+                    address,
+                    port,
+                    pins,
+                    pins_alt,
+                    route,
+                } => {
+                    let p = Rc::new(RefCell::new(Tca::new(
+                        name.to_string(),
+                        Rc::clone(&ports[port]),
+                        pins,
+                        pins_alt,
+                    )));
+                    map.push((address, p.clone()));
+                    sources.insert(name, p.clone());
+                    generators.insert(name, p.clone());
+                    routes.push((route, p.clone()));
+                    clocked.push(p.clone());
+                    tcas.insert(name, p);
                 }
+                Peripheral::Tcb {
+                    name,
+                    address,
+                    pin,
+                    pin_alt,
+                    route,
+                    tca,
+                } => {
+                    let p = Rc::new(RefCell::new(Tcb::new(
+                        name.to_string(),
+                        Rc::clone(&ports[pin.0]),
+                        pin.1,
+                        Rc::clone(&ports[pin_alt.0]),
+                        pin_alt.1,
+                        Rc::clone(&tcas[tca]),
+                    )));
+                    map.push((address, p.clone()));
+                    sources.insert(name, p.clone());
+                    generators.insert(name, p.clone());
+                    users.insert(name, p.clone());
+                    routes.push((route, p.clone()));
+                    clocked.push(p);
+                }
+                Peripheral::Adc {
+                    name,
+                    address,
+                    ain,
+                    ac,
+                } => {
+                    let p = Rc::new(RefCell::new(Adc::new(
+                        name.to_string(),
+                        clock_source.clone(),
+                        [
+                            Rc::clone(&ports[0]),
+                            Rc::clone(&ports[1]),
+                            Rc::clone(&ports[2]),
+                        ],
+                        ain,
+                        Rc::clone(&registers[ac]),
+                    )));
+                    map.push((address, p.clone()));
+                    sources.insert(name, p.clone());
+                    generators.insert(name, p.clone());
+                    users.insert(name, p.clone());
+                    clocked.push(p);
+                }
+                Peripheral::Exint {
+                    address,
+                    flags_address,
+                    pcint,
+                    int,
+                } => {
+                    let p = Rc::new(RefCell::new(Exint::new(
+                        pcint.iter().map(|&port| Rc::clone(&ports[port])).collect(),
+                        int.map(|(port, pin)| (Rc::clone(&ports[port]), pin)),
+                    )));
+                    map.push((address, p.clone()));
+                    map.push((
+                        flags_address,
+                        Rc::new(RefCell::new(ExintFlags { exint: p.clone() })),
+                    ));
+                    sources.insert("EXINT", p.clone());
+                    clocked.push(p);
+                }
+                Peripheral::Registers {
+                    name,
+                    address,
+                    size,
+                } => {
+                    let p: Rc<RefCell<dyn MemoryMapped>> =
+                        Rc::new(RefCell::new(Memory::new(size, 0x00, 0)));
+                    map.push((address, p.clone()));
+                    registers.insert(name, p);
+                }
+                Peripheral::Unimplemented {
+                    name: _,
+                    address,
+                    size,
+                } => {
+                    map.push((
+                        address,
+                        Rc::new(RefCell::new(Memory::new_rom(vec![0x00; size], 0))),
+                    ));
+                }
+                Peripheral::Unsupported {
+                    name,
+                    address,
+                    size,
+                } => {
+                    if !unsupported.contains(&name) {
+                        unsupported.push(name);
+                    }
+                    map.push((address, Rc::new(RefCell::new(Memory::new(size, 0x00, 0)))));
+                }
+            }
+        }
+        if !unsupported.is_empty() {
+            println!(
+                "[WARNING] {} of the {} are not emulated. Writes to these registers will have no effect.",
+                unsupported.join(", "),
+                desc.name
+            );
+        }
+
+        if let Some(portmux) = &portmux {
+            let mut portmux = portmux.borrow_mut();
+            for ((register, mask), peripheral) in routes {
+                portmux.add_route(register, mask, peripheral);
+            }
+        }
+
+        if let (Some(evsys), Some(events)) = (&evsys, &desc.events) {
+            let mut evsys = evsys.borrow_mut();
+            for pin in 0..8u8 {
+                for &(channels, generator, port) in events.ports {
+                    evsys.add_generator(
+                        channels,
+                        generator + pin,
+                        ports[port].clone() as Rc<RefCell<dyn EventGenerator>>,
+                        pin,
+                    );
+                }
+            }
+            for &(channels, generator, name, id) in events.generators {
+                evsys.add_generator(channels, generator, Rc::clone(&generators[name]), id);
             }
+            for &(user, name, id) in events.users {
+                evsys.add_user(user, Rc::clone(&users[name]), id);
+            }
+        }
+        if let Some(evsys) = evsys {
+            clocked.push(evsys); // Must follow event generators
+        }
+
+        for &(vector, name, mask) in desc.interrupts {
+            cpuint
+                .borrow_mut()
+                .add_source(vector, Rc::clone(&sources[name]), mask);
+        }
+
+        map.push((0x1500, stdio.clone())); // STDIO pseudo-peripheral
+        map.push((usize::from(ramend) + 1 - desc.sram_size, Rc::clone(&sram))); // SRAM ends at RAMEND
+
+        // Regions must be added in address order
+        map.sort_by_key(|(address, _)| *address);
+        let mut mm = MemoryMap::new();
+        for (address, dev) in map {
+            mm.add(address, dev);
+        }
+        let mm: Rc<RefCell<dyn MemoryMapped>> = Rc::new(RefCell::new(mm));
+
+        Device {
+            core: Core::new(
+                desc.core,
+                Rc::clone(&mm),
+                Rc::clone(&flash),
+                cpuint.clone() as Rc<RefCell<dyn InterruptHandler>>,
+                Some(slpctrl.clone()),
+                ramend,
+            ),
+            flash,
+            sram,
+            mm,
+            ports,
+            pins: desc.pins.to_vec(),
+            clock_source,
+            running: vec![true; clocked.len()],
+            clocked,
+            slpctrl,
+            sleep_mode: None,
+            stdio,
+            RAMEND: ramend,
         }
     }
 
@@ -400,10 +532,8 @@ impl Device {
                 let hex = Reader::new(&s);
                 for r in hex {
                     if let Record::Data { offset, value } = r.unwrap() {
-                        let mut address = usize::from(offset);
-                        for b in value {
+                        for (address, b) in (usize::from(offset)..).zip(value) {
                             self.flash.borrow_mut().write(address, b);
-                            address += 1;
                         }
                     }
                 }
@@ -414,8 +544,22 @@ impl Device {
     pub fn tick(&mut self, time: u64) -> u64 {
         let result = self.core.tick();
 
-        for dev in &self.clocked {
-            dev.borrow_mut().tick(time);
+        let mode = self.slpctrl.borrow().mode();
+        if mode != self.sleep_mode {
+            self.sleep_mode = mode;
+            for (dev, running) in self.clocked.iter().zip(&mut self.running) {
+                *running = dev.borrow_mut().sleep(mode);
+            }
+            let clk_per = !matches!(mode, Some(SleepMode::Standby | SleepMode::PowerDown));
+            for port in &self.ports {
+                port.borrow_mut().clk_per(clk_per);
+            }
+        }
+
+        for (dev, running) in self.clocked.iter().zip(&self.running) {
+            if *running {
+                dev.borrow_mut().tick(time);
+            }
         }
 
         if result {
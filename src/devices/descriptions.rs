@@ -0,0 +1,508 @@
+// Device descriptions: memories, peripheral instances, pin mapping and vectors.
+// Ports are indexed 0 (PORTA), 1 (PORTB), 2 (PORTC) and 3 (PORTD).
+
+use crate::cores::CoreType;
+
+pub struct DeviceDescription {
+    pub name: &'static str,
+    pub family: Family,
+    pub core: CoreType,
+    pub signature: [u8; 3],
+    pub flash_size: usize,
+    pub sram_size: usize,
+    pub eeprom_size: usize,
+    pub pins: &'static [u8], // bonded pins on each port
+    pub vectors: usize,
+    pub peripherals: &'static [Peripheral],
+    pub interrupts: &'static [(usize, &'static str, u8)], // vector, source, flag mask
+    pub events: Option<EventRouting>,
+}
+
+// System peripherals and memory layout
+pub enum Family {
+    // tinyAVR 0/1/2-series: CPU, CLKCTRL and CPUINT registers, SRAM ending at
+    // 0x3FFF and flash mapped at 0x8000
+    TinyAvr,
+    // Classic AVR: registers and I/O space from 0x0000, SRAM from 0x0100, and
+    // PINx/DDRx/PORTx from 0x0020
+    Classic,
+}
+
+// Pins are given as (port, [pins]) for the default and alternate routes, and
+// route is the PORTMUX (register, field mask) that selects between them
+pub enum Peripheral {
+    Portmux {
+        address: usize,
+    },
+    Evsys {
+        address: usize,
+    },
+    // EVSYS event output, driven by the EVSYS user of the same name
+    Evout {
+        name: &'static str,
+        pin: (usize, u8),
+        pin_alt: (usize, u8),
+        route: (usize, u8),
+    },
+    Spi {
+        name: &'static str,
+        address: usize,
+        pins: (usize, [u8; 4]),
+        pins_alt: (usize, [u8; 4]),
+        route: (usize, u8),
+    },
+    Usart {
+        name: &'static str,
+        address: usize,
+        pins: (usize, [u8; 4]),
+        pins_alt: (usize, [u8; 4]),
+        route: (usize, u8),
+    },
+    Tca {
+        name: &'static str,
+        address: usize,
+        port: usize,
+        pins: [u8; 3],
+        pins_alt: [u8; 3],
+        route: (usize, u8),
+    },
+    Tcb {
+        name: &'static str,
+        address: usize,
+        pin: (usize, u8),
+        pin_alt: (usize, u8),
+        route: (usize, u8),
+        tca: &'static str,
+    },
+    Adc {
+        name: &'static str,
+        address: usize,
+        ain: [(usize, u8); 15], // AIN1 to AIN15
+        ac: &'static str,
+    },
+    // Classic external interrupts, with PCINTn on the given ports and INT0/INT1 on
+    // the given pins
+    Exint {
+        address: usize,
+        flags_address: usize,
+        pcint: &'static [usize],
+        int: [(usize, u8); 2],
+    },
+    // Register storage only, used by other peripherals
+    Registers {
+        name: &'static str,
+        address: usize,
+        size: usize,
+    },
+    // Reads as zero, writes ignored
+    Unimplemented {
+        #[allow(dead_code)]
+        name: &'static str,
+        address: usize,
+        size: usize,
+    },
+    // Present on the device but not emulated, register storage only
+    Unsupported {
+        name: &'static str,
+        address: usize,
+        size: usize,
+    },
+}
+
+// EVSYS generators are (channel mask, generator, source, id), with port pin
+// generators given as (channel mask, first generator, port)
+pub struct EventRouting {
+    pub ports: &'static [(u8, u8, usize)],
+    pub generators: &'static [(u8, u8, &'static str, u8)],
+    pub users: &'static [(usize, &'static str, u8)],
+}
+
+// tinyAVR 2-series
+
+const TINYAVR2_PERIPHERALS: &[Peripheral] = &[
+    Peripheral::Evsys { address: 0x0180 },
+    Peripheral::Evout {
+        name: "EVOUTA",
+        pin: (0, 2),
+        pin_alt: (0, 7),
+        route: (0x00, 0x01), // EVSYSROUTEA
+    },
+    Peripheral::Evout {
+        name: "EVOUTB",
+        pin: (1, 2),
+        pin_alt: (1, 7),
+        route: (0x00, 0x02), // EVSYSROUTEA
+    },
+    Peripheral::Evout {
+        name: "EVOUTC",
+        pin: (2, 2),
+        pin_alt: (2, 7),
+        route: (0x00, 0x04), // EVSYSROUTEA
+    },
+    Peripheral::Portmux { address: 0x05E0 },
+    Peripheral::Registers {
+        name: "AC0",
+        address: 0x0680,
+        size: 0x08,
+    },
+    Peripheral::Unimplemented {
+        name: "TWI0",
+        address: 0x08A0,
+        size: 0x0F,
+    },
+    Peripheral::Spi {
+        name: "SPI0",
+        address: 0x08C0,
+        pins: (0, [1, 2, 3, 4]),
+        pins_alt: (2, [2, 1, 0, 3]),
+        route: (0x03, 0x03), // SPIROUTEA
+    },
+    Peripheral::Tca {
+        name: "TCA0",
+        address: 0x0A00,
+        port: 1,
+        pins: [0, 1, 2],
+        pins_alt: [3, 4, 5],
+        route: (0x04, 0x3F), // TCAROUTEA
+    },
+    Peripheral::Tcb {
+        name: "TCB0",
+        address: 0x0A80,
+        pin: (0, 5),
+        pin_alt: (2, 0),
+        route: (0x05, 0x01), // TCBROUTEA
+        tca: "TCA0",
+    },
+    Peripheral::Tcb {
+        name: "TCB1",
+        address: 0x0A90,
+        pin: (0, 3),
+        pin_alt: (2, 4),
+        route: (0x05, 0x02), // TCBROUTEA
+        tca: "TCA0",
+    },
+    Peripheral::Adc {
+        name: "ADC0",
+        address: 0x0600,
+        ain: [
+            (0, 1),
+            (0, 2),
+            (0, 3),
+            (0, 4),
+            (0, 5),
+            (0, 6),
+            (0, 7),
+            (1, 5),
+            (1, 4),
+            (1, 1),
+            (1, 0),
+            (2, 0),
+            (2, 1),
+            (2, 2),
+            (2, 3),
+        ],
+        ac: "AC0",
+    },
+    Peripheral::Usart {
+        name: "USART0",
+        address: 0x0800,
+        pins: (1, [3, 2, 1, 0]),
+        pins_alt: (0, [2, 1, 3, 4]),
+        route: (0x02, 0x03), // USARTROUTEA
+    },
+    Peripheral::Usart {
+        name: "USART1",
+        address: 0x0820,
+        pins: (0, [2, 1, 3, 4]),
+        pins_alt: (2, [1, 2, 0, 3]),
+        route: (0x02, 0x0C), // USARTROUTEA
+    },
+];
+
+const TINYAVR2_INTERRUPTS: &[(usize, &str, u8)] = &[
+    (8, "TCA0", 0x01),    // OVF
+    (10, "TCA0", 0x10),   // CMP0
+    (11, "TCA0", 0x20),   // CMP1
+    (12, "TCA0", 0x40),   // CMP2
+    (13, "TCB0", 0x03),   // INT
+    (16, "SPI0", 0xF1),   // INT
+    (17, "USART0", 0x98), // RXC, RXS, ISF
+    (18, "USART0", 0x20), // DRE
+    (19, "USART0", 0x40), // TXC
+    (21, "ADC0", 0x38),   // RESOVR, SAMPOVR, TRIGOVR
+    (22, "ADC0", 0x05),   // RESRDY, WCMP (WINSRC = RESULT)
+    (23, "ADC0", 0x06),   // SAMPRDY, WCMP (WINSRC = SAMPLE)
+    (25, "TCB1", 0x03),   // INT
+    (26, "USART1", 0x98), // RXC, RXS, ISF
+    (27, "USART1", 0x20), // DRE
+    (28, "USART1", 0x40), // TXC
+    (6, "PORTA", 0xFF),
+    (7, "PORTB", 0xFF),
+    (24, "PORTC", 0xFF),
+];
+
+const TINYAVR2_EVENTS: EventRouting = EventRouting {
+    // Port pin generators are encoded per channel pair
+    ports: &[
+        (0x03, 0x40, 0),
+        (0x03, 0x48, 1),
+        (0x0C, 0x40, 2),
+        (0x0C, 0x48, 0),
+        (0x30, 0x40, 1),
+        (0x30, 0x48, 2),
+    ],
+    generators: &[
+        (0x3F, 0x80, "TCA0", 0), // OVF_LUNF
+        (0x3F, 0x84, "TCA0", 4), // CMP0_LCMP0
+        (0x3F, 0x85, "TCA0", 5), // CMP1_LCMP1
+        (0x3F, 0x86, "TCA0", 6), // CMP2_LCMP2
+        (0x3F, 0xA0, "TCB0", 0), // CAPT
+        (0x3F, 0xA1, "TCB0", 1), // OVF
+        (0x3F, 0xA2, "TCB1", 0), // CAPT
+        (0x3F, 0xA3, "TCB1", 1), // OVF
+        (0x3F, 0x24, "ADC0", 0), // RESRDY
+        (0x3F, 0x25, "ADC0", 1), // SAMPRDY
+        (0x3F, 0x26, "ADC0", 2), // WCMP
+    ],
+    users: &[
+        (0x28, "ADC0", 0),   // START
+        (0x29, "EVOUTA", 0), // EVOUTA
+        (0x2A, "EVOUTB", 0), // EVOUTB
+        (0x2B, "EVOUTC", 0), // EVOUTC
+        (0x30, "TCB0", 0),   // CAPT
+        (0x31, "TCB0", 1),   // COUNT
+        (0x32, "TCB1", 0),   // CAPT
+        (0x33, "TCB1", 1),   // COUNT
+    ],
+};
+
+pub const ATTINY3227: DeviceDescription = DeviceDescription {
+    name: "ATtiny3227",
+    family: Family::TinyAvr,
+    core: CoreType::AVRxt,
+    signature: [0x1E, 0x95, 0x26],
+    flash_size: 32 * 1024,
+    sram_size: 3 * 1024,
+    eeprom_size: 256,
+    pins: &[8, 8, 6],
+    vectors: 30,
+    peripherals: TINYAVR2_PERIPHERALS,
+    interrupts: TINYAVR2_INTERRUPTS,
+    events: Some(TINYAVR2_EVENTS),
+};
+
+pub const ATTINY3226: DeviceDescription = DeviceDescription {
+    name: "ATtiny3226",
+    signature: [0x1E, 0x95, 0x27],
+    pins: &[8, 6, 4],
+    ..ATTINY3227
+};
+
+pub const ATTINY1627: DeviceDescription = DeviceDescription {
+    name: "ATtiny1627",
+    signature: [0x1E, 0x94, 0x28],
+    flash_size: 16 * 1024,
+    sram_size: 2 * 1024,
+    ..ATTINY3227
+};
+
+pub const ATTINY1626: DeviceDescription = DeviceDescription {
+    name: "ATtiny1626",
+    signature: [0x1E, 0x94, 0x29],
+    pins: &[8, 6, 4],
+    ..ATTINY1627
+};
+
+pub const ATTINY826: DeviceDescription = DeviceDescription {
+    name: "ATtiny826",
+    signature: [0x1E, 0x93, 0x28],
+    flash_size: 8 * 1024,
+    sram_size: 1024,
+    eeprom_size: 128,
+    ..ATTINY1626
+};
+
+// tinyAVR 1-series (16 and 32 KB). The ADC, AC, DAC, EVSYS and TCD differ
+// from the 2-series and are not emulated.
+
+const TINYAVR1_PERIPHERALS: &[Peripheral] = &[
+    Peripheral::Unsupported {
+        name: "EVSYS",
+        address: 0x0180,
+        size: 0x34,
+    },
+    Peripheral::Portmux { address: 0x0200 },
+    Peripheral::Unsupported {
+        name: "ADC0",
+        address: 0x0600,
+        size: 0x20,
+    },
+    Peripheral::Unsupported {
+        name: "ADC1",
+        address: 0x0640,
+        size: 0x20,
+    },
+    Peripheral::Unsupported {
+        name: "AC0",
+        address: 0x0680,
+        size: 0x08,
+    },
+    Peripheral::Unsupported {
+        name: "DAC0",
+        address: 0x06A0,
+        size: 0x02,
+    },
+    Peripheral::Unimplemented {
+        name: "TWI0",
+        address: 0x0810,
+        size: 0x0F,
+    },
+    Peripheral::Spi {
+        name: "SPI0",
+        address: 0x0820,
+        pins: (0, [1, 2, 3, 4]),
+        pins_alt: (2, [2, 1, 0, 3]),
+        route: (0x01, 0x04), // CTRLB
+    },
+    Peripheral::Tca {
+        name: "TCA0",
+        address: 0x0A00,
+        port: 1,
+        pins: [0, 1, 2],
+        pins_alt: [3, 4, 5],
+        route: (0x02, 0x3F), // CTRLC
+    },
+    Peripheral::Tcb {
+        name: "TCB0",
+        address: 0x0A40,
+        pin: (0, 5),
+        pin_alt: (2, 0),
+        route: (0x03, 0x01), // CTRLD
+        tca: "TCA0",
+    },
+    Peripheral::Tcb {
+        name: "TCB1",
+        address: 0x0A50,
+        pin: (0, 3),
+        pin_alt: (2, 4),
+        route: (0x03, 0x02), // CTRLD
+        tca: "TCA0",
+    },
+    Peripheral::Unsupported {
+        name: "TCD0",
+        address: 0x0A80,
+        size: 0x50,
+    },
+    Peripheral::Usart {
+        name: "USART0",
+        address: 0x0800,
+        pins: (1, [3, 2, 1, 0]),
+        pins_alt: (0, [2, 1, 3, 4]),
+        route: (0x01, 0x01), // CTRLB
+    },
+];
+
+const TINYAVR1_INTERRUPTS: &[(usize, &str, u8)] = &[
+    (8, "TCA0", 0x01),    // OVF
+    (10, "TCA0", 0x10),   // CMP0
+    (11, "TCA0", 0x20),   // CMP1
+    (12, "TCA0", 0x40),   // CMP2
+    (13, "TCB0", 0x03),   // INT
+    (14, "TCB1", 0x03),   // INT
+    (26, "SPI0", 0xF1),   // INT
+    (27, "USART0", 0x98), // RXC, RXS, ISF
+    (28, "USART0", 0x20), // DRE
+    (29, "USART0", 0x40), // TXC
+    (3, "PORTA", 0xFF),
+    (4, "PORTB", 0xFF),
+    (5, "PORTC", 0xFF),
+];
+
+pub const ATTINY3216: DeviceDescription = DeviceDescription {
+    name: "ATtiny3216",
+    family: Family::TinyAvr,
+    core: CoreType::AVRxt,
+    signature: [0x1E, 0x95, 0x21],
+    flash_size: 32 * 1024,
+    sram_size: 2 * 1024,
+    eeprom_size: 256,
+    pins: &[8, 6, 4],
+    vectors: 31,
+    peripherals: TINYAVR1_PERIPHERALS,
+    interrupts: TINYAVR1_INTERRUPTS,
+    events: None,
+};
+
+pub const ATTINY1614: DeviceDescription = DeviceDescription {
+    name: "ATtiny1614",
+    signature: [0x1E, 0x94, 0x22],
+    flash_size: 16 * 1024,
+    pins: &[8, 4, 0],
+    ..ATTINY3216
+};
+
+// megaAVR (classic). Only the core, ports, external interrupts, sleep mode
+// control and clock prescaler are emulated.
+
+const ATMEGA328P_PERIPHERALS: &[Peripheral] = &[
+    Peripheral::Exint {
+        address: 0x0068,
+        flags_address: 0x003B,
+        pcint: &[1, 2, 3],
+        int: [(3, 2), (3, 3)],
+    },
+    Peripheral::Unsupported {
+        name: "I/O registers",
+        address: 0x002C,
+        size: 0x0F,
+    },
+    Peripheral::Unsupported {
+        name: "I/O registers",
+        address: 0x003E,
+        size: 0x15,
+    },
+    Peripheral::Unsupported {
+        name: "I/O registers",
+        address: 0x0054,
+        size: 0x09,
+    },
+    Peripheral::Unsupported {
+        name: "WDT",
+        address: 0x0060,
+        size: 0x01,
+    },
+    Peripheral::Unsupported {
+        name: "extended I/O registers",
+        address: 0x0062,
+        size: 0x06,
+    },
+    Peripheral::Unsupported {
+        name: "extended I/O registers",
+        address: 0x006E,
+        size: 0x92,
+    },
+];
+
+// Only the sources that are emulated, in priority order
+const ATMEGA328P_INTERRUPTS: &[(usize, &str, u8)] = &[
+    (1, "EXINT", 0x01), // INT0
+    (2, "EXINT", 0x02), // INT1
+    (3, "EXINT", 0x04), // PCINT0
+    (4, "EXINT", 0x08), // PCINT1
+    (5, "EXINT", 0x10), // PCINT2
+];
+
+pub const ATMEGA328P: DeviceDescription = DeviceDescription {
+    name: "ATmega328P",
+    family: Family::Classic,
+    core: CoreType::AVReplus,
+    signature: [0x1E, 0x95, 0x0F],
+    flash_size: 32 * 1024,
+    sram_size: 2 * 1024,
+    eeprom_size: 1024,
+    pins: &[0, 8, 7, 8],
+    vectors: 26,
+    peripherals: ATMEGA328P_PERIPHERALS,
+    interrupts: ATMEGA328P_INTERRUPTS,
+    events: None,
+};
@@ -12,20 +12,15 @@ pub struct Event {
 
 impl Event {
     pub fn from_file(filename: &str) -> Events {
-        let file_events = fs::read_to_string(filename);
-
         let mut events = Vec::new();
 
-        if file_events.is_err() {
-            println!(
-                "[EVENTS] Couldn't open {}. {}",
-                filename,
-                file_events.unwrap_err()
-            );
-            return events;
-        }
-
-        let event_str = file_events.unwrap();
+        let event_str = match fs::read_to_string(filename) {
+            Ok(event_str) => event_str,
+            Err(error) => {
+                println!("[EVENTS] Couldn't open {}. {}", filename, error);
+                return events;
+            }
+        };
         let re_events = Regex::new("@([0-9A-F-a-f]+)\\s+(.+):\\s+(.+)\\n+").unwrap();
         let caps_events = re_events.captures_iter(&event_str);
 
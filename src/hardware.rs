@@ -1,10 +1,13 @@
 pub mod display;
+pub mod hostspi;
 pub mod ic74hc595;
 pub mod led;
 pub mod pot;
 pub mod pushbutton;
+pub mod resistor;
 pub mod sinkpwm;
 pub mod sinkuart;
+pub mod uartbridge;
 
 pub trait Hardware {
     fn update(&mut self, time: u64);
@@ -53,9 +53,10 @@ impl Display {
 
     pub fn connect_seg(&mut self, n: usize, net: Rc<RefCell<Net>>) {
         self.nets_segs[n] = net;
-        self.nets_segs[n]
-            .borrow_mut()
-            .connect(Rc::downgrade(&self.pins_segs[n]));
+        self.nets_segs[n].borrow_mut().connect(
+            Rc::downgrade(&self.pins_segs[n]),
+            format!("{}.SEG{}", self.name, n),
+        );
     }
 
     pub fn connect(&mut self, pin_name: &str, net: Rc<RefCell<Net>>) {
@@ -64,13 +65,14 @@ impl Display {
                 self.net_en = net;
                 self.net_en
                     .borrow_mut()
-                    .connect(Rc::downgrade(&self.pin_en))
+                    .connect(Rc::downgrade(&self.pin_en), format!("{}.EN", self.name))
             }
             "digit" => {
                 self.net_digit = net;
-                self.net_digit
-                    .borrow_mut()
-                    .connect(Rc::downgrade(&self.pin_digit))
+                self.net_digit.borrow_mut().connect(
+                    Rc::downgrade(&self.pin_digit),
+                    format!("{}.DIGIT", self.name),
+                )
             }
             _ => {}
         }
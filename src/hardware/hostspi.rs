@@ -0,0 +1,186 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use bitvec::prelude::*;
+
+use super::Hardware;
+use crate::nets::{Net, NetState, PinState};
+
+#[derive(Debug, PartialEq)]
+enum SpiState {
+    Idle,
+    Shift,
+    Deselect,
+    Release,
+}
+
+// Emulated SPI host, MSB first. Pins are only driven during a transaction
+// so that the bus remains available to the microcontroller.
+pub struct HostSPI {
+    name: String,
+    pin_sck: Rc<RefCell<PinState>>,
+    pin_mosi: Rc<RefCell<PinState>>,
+    pin_ss: Rc<RefCell<PinState>>,
+    net_miso: Rc<RefCell<Net>>,
+    state: SpiState,
+    mode: u8,
+    queue: VecDeque<u8>,
+    edge: u8,
+    tx_reg: u8,
+    rx_reg: u8,
+    time: u64,
+    ns_per_half: u64,
+}
+
+impl HostSPI {
+    pub fn new(
+        name: String,
+        sck: Rc<RefCell<Net>>,
+        mosi: Rc<RefCell<Net>>,
+        miso: Rc<RefCell<Net>>,
+        ss: Rc<RefCell<Net>>,
+    ) -> Self {
+        let host = HostSPI {
+            name,
+            pin_sck: Rc::new(RefCell::new(PinState::Open)),
+            pin_mosi: Rc::new(RefCell::new(PinState::Open)),
+            pin_ss: Rc::new(RefCell::new(PinState::Open)),
+            net_miso: miso,
+            state: SpiState::Idle,
+            mode: 0,
+            queue: VecDeque::new(),
+            edge: 0,
+            tx_reg: 0,
+            rx_reg: 0,
+            time: 0,
+            ns_per_half: 5000, // 100 kHz
+        };
+        sck.borrow_mut()
+            .connect(Rc::downgrade(&host.pin_sck), format!("{}.SCK", host.name));
+        mosi.borrow_mut()
+            .connect(Rc::downgrade(&host.pin_mosi), format!("{}.MOSI", host.name));
+        ss.borrow_mut()
+            .connect(Rc::downgrade(&host.pin_ss), format!("{}.SS", host.name));
+        host
+    }
+
+    fn drive(pin: &Rc<RefCell<PinState>>, state: bool) {
+        *pin.borrow_mut() = if state {
+            PinState::DriveH
+        } else {
+            PinState::DriveL
+        };
+    }
+
+    fn cpol(&self) -> bool {
+        (self.mode & 0x02) != 0
+    }
+
+    fn cpha(&self) -> bool {
+        (self.mode & 0x01) != 0
+    }
+
+    fn setup(&mut self) {
+        let bit = self.tx_reg.view_bits::<Msb0>()[usize::from(self.edge >> 1)];
+        Self::drive(&self.pin_mosi, bit);
+    }
+
+    fn load(&mut self, byte: u8) {
+        self.tx_reg = byte;
+        self.rx_reg = 0;
+        self.edge = 0;
+        if !self.cpha() {
+            // First bit must be valid before the first leading edge
+            self.setup();
+        }
+    }
+}
+
+impl Hardware for HostSPI {
+    fn update(&mut self, time: u64) {
+        if self.state.eq(&SpiState::Idle) | (time < self.time) {
+            return;
+        }
+        self.time += self.ns_per_half;
+
+        match self.state {
+            SpiState::Shift => {
+                self.edge += 1;
+                let leading = (self.edge & 1) == 1;
+                Self::drive(&self.pin_sck, leading != self.cpol());
+                if leading != self.cpha() {
+                    // Sample
+                    let miso = self.net_miso.borrow().state.eq(&NetState::High);
+                    let index = usize::from((self.edge - 1) >> 1);
+                    self.rx_reg.view_bits_mut::<Msb0>().set(index, miso);
+                } else if self.edge < 16 {
+                    self.setup();
+                }
+                if self.edge == 16 {
+                    println!(
+                        "[@{:012X}] SPI|{}: Tx 0x{:02X} Rx 0x{:02X}",
+                        time, self.name, self.tx_reg, self.rx_reg
+                    );
+                    match self.queue.pop_front() {
+                        Some(byte) => self.load(byte),
+                        None => self.state = SpiState::Deselect,
+                    }
+                }
+            }
+            SpiState::Deselect => {
+                Self::drive(&self.pin_ss, true);
+                self.state = SpiState::Release;
+            }
+            SpiState::Release => {
+                *self.pin_sck.borrow_mut() = PinState::Open;
+                *self.pin_mosi.borrow_mut() = PinState::Open;
+                *self.pin_ss.borrow_mut() = PinState::Open;
+                self.state = SpiState::Idle;
+            }
+            SpiState::Idle => {}
+        }
+    }
+
+    fn event(&mut self, time: u64, event: &str) {
+        // Either "mode n", or a transaction of hex bytes separated by spaces
+        if !self.state.eq(&SpiState::Idle) {
+            println!(
+                "[@{:012X}] SPI|{}: Transaction in progress, event ignored.",
+                time, self.name
+            );
+        } else if let Some(mode) = event.strip_prefix("mode") {
+            match mode.trim().parse::<u8>() {
+                Ok(mode) if mode <= 3 => self.mode = mode,
+                _ => println!(
+                    "[@{:012X}] SPI|{}: Invalid mode {}, event ignored.",
+                    time,
+                    self.name,
+                    mode.trim()
+                ),
+            }
+        } else {
+            let mut bytes = VecDeque::new();
+            for byte in event.split_whitespace() {
+                match u8::from_str_radix(byte, 16) {
+                    Ok(byte) => bytes.push_back(byte),
+                    Err(_) => {
+                        println!(
+                            "[@{:012X}] SPI|{}: Invalid data {}, event ignored.",
+                            time, self.name, byte
+                        );
+                        return;
+                    }
+                }
+            }
+            if let Some(byte) = bytes.pop_front() {
+                self.queue = bytes;
+                Self::drive(&self.pin_sck, self.cpol());
+                Self::drive(&self.pin_ss, false);
+                self.load(byte);
+                self.state = SpiState::Shift;
+                self.time = time + self.ns_per_half;
+            }
+        }
+    }
+}
@@ -69,9 +69,10 @@ impl IC74HC595 {
 
     pub fn connect_q(&mut self, n: usize, net: Rc<RefCell<Net>>) {
         self.nets_out[n] = net;
-        self.nets_out[n]
-            .borrow_mut()
-            .connect(Rc::downgrade(&self.pins_out[n]));
+        self.nets_out[n].borrow_mut().connect(
+            Rc::downgrade(&self.pins_out[n]),
+            format!("{}.Q{}", self.name, n),
+        );
     }
 
     pub fn connect(&mut self, pin_name: &str, net: Rc<RefCell<Net>>) {
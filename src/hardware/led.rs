@@ -2,7 +2,11 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use super::Hardware;
-use crate::nets::{Net, NetState, PinState};
+use crate::nets::{Net, PinState, VDD};
+
+// Forward voltage and series resistance of the LED
+const LED_VF: f32 = 1.8;
+const LED_R: f32 = 1000.0;
 
 #[derive(Debug, PartialEq)]
 enum LedState {
@@ -23,16 +27,14 @@ impl Led {
     pub fn new(name: String, active_high: bool, net: Rc<RefCell<Net>>) -> Self {
         let mut led = Led {
             name,
-            pin: if active_high {
-                Rc::new(RefCell::new(PinState::WeakPullDown))
-            } else {
-                Rc::new(RefCell::new(PinState::WeakPullUp))
-            },
+            pin: Rc::new(RefCell::new(PinState::Open)),
             net,
             state: LedState::Undefined,
             active_high,
         };
-        led.net.borrow_mut().connect(Rc::downgrade(&led.pin));
+        led.net
+            .borrow_mut()
+            .connect(Rc::downgrade(&led.pin), led.name.clone());
         led.update(0);
         led
     }
@@ -40,24 +42,30 @@ impl Led {
 
 impl Hardware for Led {
     fn update(&mut self, time: u64) {
-        let new_state: LedState;
-        match self.net.borrow().state {
-            NetState::High => {
-                if self.active_high {
-                    new_state = LedState::On
-                } else {
-                    new_state = LedState::Off
-                }
-            }
-            NetState::Low => {
-                if !self.active_high {
-                    new_state = LedState::On
-                } else {
-                    new_state = LedState::Off
-                }
-            }
-            _ => new_state = LedState::Undefined,
-        }
+        // The LED conducts once the voltage across it exceeds the forward voltage,
+        // a floating net cannot forward bias it
+        let (voltage, floating) = {
+            let net = self.net.borrow();
+            (net.voltage, net.floating)
+        };
+        let across = if self.active_high {
+            voltage
+        } else {
+            VDD - voltage
+        };
+        let new_state = if !floating & (across > LED_VF) {
+            LedState::On
+        } else {
+            LedState::Off
+        };
+
+        // While conducting, the LED and its resistor load the net, otherwise
+        // the reverse biased LED is high impedance
+        *self.pin.borrow_mut() = match (new_state.eq(&LedState::On), self.active_high) {
+            (true, true) => PinState::Resistive(LED_VF, LED_R),
+            (true, false) => PinState::Resistive(VDD - LED_VF, LED_R),
+            (false, _) => PinState::Open,
+        };
         if !self.state.eq(&new_state) {
             if time > 0 {
                 println!("[@{:012X}] LED|{}: {:?}", time, self.name, new_state);
@@ -15,13 +15,14 @@ impl Pot {
             name,
             pin: Rc::new(RefCell::new(PinState::DriveAnalog(0.0))),
         };
-        net.borrow_mut().connect(Rc::downgrade(&pot.pin));
+        net.borrow_mut()
+            .connect(Rc::downgrade(&pot.pin), pot.name.clone());
         pot.set(0, position);
         pot
     }
 
     fn set(&mut self, time: u64, position: f32) {
-        let pos = position.min(1.0).max(0.0);
+        let pos = position.clamp(0.0, 1.0);
 
         if time > 0 {
             println!("[@{:012X}] POT|{}: {:.3}", time, self.name, pos);
@@ -26,7 +26,8 @@ impl Pushbutton {
             state: PushbuttonState::Released,
             active_high,
         };
-        net.borrow_mut().connect(Rc::downgrade(&pb.pin));
+        net.borrow_mut()
+            .connect(Rc::downgrade(&pb.pin), pb.name.clone());
         pb
     }
 
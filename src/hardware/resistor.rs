@@ -0,0 +1,24 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::Hardware;
+use crate::nets::{Net, PinState};
+
+// Resistor from a net to a fixed voltage, e.g. an external pull-up or pull-down
+pub struct Resistor {
+    pin: Rc<RefCell<PinState>>,
+}
+
+impl Resistor {
+    pub fn new(name: String, net: Rc<RefCell<Net>>, volts: f32, ohms: f32) -> Self {
+        let r = Resistor {
+            pin: Rc::new(RefCell::new(PinState::Resistive(volts, ohms))),
+        };
+        net.borrow_mut().connect(Rc::downgrade(&r.pin), name);
+        r
+    }
+}
+
+impl Hardware for Resistor {
+    fn update(&mut self, _time: u64) {}
+}
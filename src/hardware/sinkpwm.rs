@@ -40,7 +40,9 @@ impl SinkPwm {
             is_dc: true,
             desc: String::new(),
         };
-        sink.net.borrow_mut().connect(Rc::downgrade(&sink.pin));
+        sink.net
+            .borrow_mut()
+            .connect(Rc::downgrade(&sink.pin), sink.name.clone());
         sink.update(0);
         sink
     }
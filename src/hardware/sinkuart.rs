@@ -2,10 +2,10 @@ use std::cell::RefCell;
 use std::fs;
 use std::rc::Rc;
 
-use bitvec::prelude::*;
-
+use super::uartbridge::UartBridge;
 use super::Hardware;
 use crate::nets::{Net, NetState, PinState};
+use crate::CLI;
 
 #[derive(Debug, PartialEq)]
 enum RxState {
@@ -21,6 +21,54 @@ enum UartState {
     Check,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, PartialEq)]
+enum Fault {
+    None,
+    Framing,
+    Parity,
+}
+
+// Format is <data bits><parity><stop bits>, e.g. 8N1 or 7E2
+fn parse_format(format: &str) -> Option<(u8, Parity, u8)> {
+    let format = format.trim().to_ascii_uppercase();
+    let chars: Vec<char> = format.chars().collect();
+    if chars.len() != 3 {
+        return None;
+    }
+    let data_bits = match chars[0].to_digit(10) {
+        Some(n @ 5..=9) => n as u8,
+        _ => return None,
+    };
+    let parity = match chars[1] {
+        'N' => Parity::None,
+        'E' => Parity::Even,
+        'O' => Parity::Odd,
+        _ => return None,
+    };
+    let stop_bits = match chars[2].to_digit(10) {
+        Some(n @ 1..=2) => n as u8,
+        _ => return None,
+    };
+    Some((data_bits, parity, stop_bits))
+}
+
+// Validates the frame format given on the command line
+pub fn validate_format(format: &str) -> Result<String, String> {
+    match parse_format(format) {
+        Some(_) => Ok(format.to_string()),
+        None => {
+            Err("expected <data bits 5-9><parity N, E or O><stop bits 1-2>, e.g. 8N1".to_string())
+        }
+    }
+}
+
 pub struct SinkUART {
     name: String,
     pin_tx: Rc<RefCell<PinState>>,
@@ -30,18 +78,26 @@ pub struct SinkUART {
     tx_state: UartState,
     rx_bit: u8,
     tx_bit: u8,
-    rx_reg: u16,
-    tx_reg: u16,
+    rx_reg: u32,
+    tx_reg: u32,
     rx_time: u64,
     tx_time: u64,
     ns_per_bit: u64,
+    tx_ns_per_bit: u64,
+    mismatch: f64,
+    data_bits: u8,
+    parity: Parity,
+    stop_bits: u8,
+    glitch_time: u64,
+    bridge: Option<Box<dyn UartBridge>>,
+    bridge_time: u64,
     out: String,
     outfile: String,
 }
 
 impl SinkUART {
     pub fn new(name: String, rx: Rc<RefCell<Net>>, tx: Rc<RefCell<Net>>, filename: String) -> Self {
-        let su = SinkUART {
+        let mut su = SinkUART {
             name,
             pin_tx: Rc::new(RefCell::new(PinState::DriveH)),
             net_rx: rx,
@@ -55,13 +111,52 @@ impl SinkUART {
             rx_time: 0,
             tx_time: 0,
             ns_per_bit: 104167,
+            tx_ns_per_bit: 104167,
+            mismatch: 0.0,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            glitch_time: 0,
+            bridge: None,
+            bridge_time: 0,
             out: "".to_string(),
             outfile: filename,
         };
-        tx.borrow_mut().connect(Rc::downgrade(&su.pin_tx));
+        su.set_baud(CLI.uart_baud);
+        su.set_format(&CLI.uart_format); // validated when the arguments are parsed
+        tx.borrow_mut()
+            .connect(Rc::downgrade(&su.pin_tx), format!("{}.TX", su.name));
         su
     }
 
+    pub fn set_bridge(&mut self, bridge: Box<dyn UartBridge>) {
+        self.bridge = Some(bridge);
+    }
+
+    fn set_baud(&mut self, baud: u32) {
+        self.ns_per_bit = 1_000_000_000 / u64::from(baud.max(1));
+    }
+
+    fn set_format(&mut self, format: &str) -> bool {
+        match parse_format(format) {
+            Some((data_bits, parity, stop_bits)) => {
+                self.data_bits = data_bits;
+                self.parity = parity;
+                self.stop_bits = stop_bits;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn parity_bit(&self, data: u16) -> bool {
+        let odd = (data.count_ones() & 1) == 1;
+        match self.parity {
+            Parity::Odd => !odd,
+            _ => odd,
+        }
+    }
+
     fn out(&mut self, c: u8) {
         self.out.push(c as char);
     }
@@ -71,15 +166,85 @@ impl SinkUART {
             .unwrap_or_else(|_| panic!("Unable to write uart out to {}.", self.outfile));
     }
 
-    fn tx(&mut self, time: u64, byte: u8) {
+    fn tx(&mut self, time: u64, data: u16, fault: Fault) {
+        let mask = (1u16 << self.data_bits) - 1;
+        let data = data & mask;
+        let mut reg = data as u32;
+        let mut bits = self.data_bits;
+        if self.parity.ne(&Parity::None) {
+            let mut parity = self.parity_bit(data);
+            if fault.eq(&Fault::Parity) {
+                parity = !parity;
+            }
+            reg |= (parity as u32) << bits;
+            bits += 1;
+        }
+        for i in 0..self.stop_bits {
+            // A framing error is injected by holding the first stop bit low
+            if (i > 0) | fault.ne(&Fault::Framing) {
+                reg |= 1 << bits;
+            }
+            bits += 1;
+        }
+        if fault.eq(&Fault::Framing) {
+            // Return the line to idle after the faulty frame
+            reg |= 1 << bits;
+            bits += 1;
+        }
+        self.send(time, reg, bits);
+    }
+
+    fn send(&mut self, time: u64, reg: u32, bits: u8) {
+        // Drives the start bit, then shifts out bits of reg LSB first
         if self.tx_state.eq(&UartState::Idle) {
-            let mut b = byte as u16;
-            b |= 0x0100; // stop bit
-            self.tx_reg = b;
-            self.tx_bit = 9; // start + 8N1
+            self.tx_reg = reg;
+            self.tx_bit = bits;
             self.tx_state = UartState::Shift;
-            self.tx_time = time + self.ns_per_bit;
+            self.tx_ns_per_bit = (self.ns_per_bit as f64 * (1.0 + self.mismatch / 100.0)) as u64;
+            self.tx_time = time + self.tx_ns_per_bit;
             *self.pin_tx.borrow_mut() = PinState::DriveL;
+        } else {
+            println!(
+                "[@{:012X}] UART|{}: Transmission in progress, event ignored.",
+                time, self.name
+            );
+        }
+    }
+
+    fn rx_frame(&mut self, time: u64) {
+        // rx_reg holds data, parity and stop bit, LSB first
+        let mask = (1u32 << self.data_bits) - 1;
+        let data = (self.rx_reg & mask) as u16;
+        let mut index = self.data_bits;
+        let mut fault = Fault::None;
+        if self.parity.ne(&Parity::None) {
+            if ((self.rx_reg >> index) & 1 == 1) != self.parity_bit(data) {
+                fault = Fault::Parity;
+            }
+            index += 1;
+        }
+        if (self.rx_reg >> index) & 1 == 0 {
+            fault = Fault::Framing; // Stop bit is low
+        }
+        if fault.ne(&Fault::None) {
+            // Received bits are printed stop bit first
+            println!(
+                "[@{:012X}] UART|{}: {} error, received 0b{:0width$b}, frame discarded.",
+                time,
+                self.name,
+                if fault.eq(&Fault::Framing) {
+                    "Framing"
+                } else {
+                    "Parity"
+                },
+                self.rx_reg,
+                width = usize::from(index) + 1
+            );
+        } else {
+            self.out(data as u8);
+            if let Some(bridge) = &mut self.bridge {
+                bridge.write(data as u8);
+            }
         }
     }
 }
@@ -96,7 +261,8 @@ impl Hardware for SinkUART {
             UartState::Idle => {
                 if rx_pinstate_new.eq(&RxState::Low) & self.rx_pinstate.eq(&RxState::High) {
                     self.rx_state = UartState::Shift;
-                    self.rx_bit = 9; // 8N1
+                    self.rx_bit = 0;
+                    self.rx_reg = 0;
                     self.rx_time = time + (self.ns_per_bit * 3 / 2);
                 }
             }
@@ -104,42 +270,51 @@ impl Hardware for SinkUART {
                 if time >= self.rx_time {
                     self.rx_time += self.ns_per_bit;
                     if rx_pinstate_new.eq(&RxState::High) {
-                        self.rx_reg |= 1;
+                        self.rx_reg |= 1 << self.rx_bit;
                     }
-                    self.rx_bit -= 1;
-                    if self.rx_bit == 0 {
+                    self.rx_bit += 1;
+                    // data + parity + first stop bit
+                    let parity = self.parity.ne(&Parity::None) as u8;
+                    if self.rx_bit == self.data_bits + parity + 1 {
                         self.rx_state = UartState::Check;
-                    } else {
-                        self.rx_reg <<= 1;
                     }
                 }
             }
             UartState::Check => {
-                if self.rx_reg & 1 == 1 {
-                    // Stop bit is high, frame OK
-                    let mut byte = (self.rx_reg >> 1) as u8;
-                    byte.view_bits_mut::<Lsb0>().reverse();
-                    self.out(byte);
-                }
-                self.rx_reg = 0;
+                self.rx_frame(time);
                 self.rx_state = UartState::Idle;
             }
         };
 
+        if (self.glitch_time > 0) & (time >= self.glitch_time) {
+            self.glitch_time = 0;
+            *self.pin_tx.borrow_mut() = PinState::DriveH;
+        }
+
         match self.tx_state {
-            UartState::Idle => {}
+            UartState::Idle => {
+                // Poll the bridge at most once per bit period
+                if (self.glitch_time == 0) & (time >= self.bridge_time) {
+                    self.bridge_time = time + self.ns_per_bit;
+                    if let Some(byte) = self.bridge.as_mut().and_then(|bridge| bridge.read()) {
+                        self.tx(time, byte as u16, Fault::None);
+                    }
+                }
+            }
             UartState::Shift => {
                 if time >= self.tx_time {
-                    self.tx_time += self.ns_per_bit;
-                    if (self.tx_reg & 1) == 1 {
-                        *self.pin_tx.borrow_mut() = PinState::DriveH;
-                    } else {
-                        *self.pin_tx.borrow_mut() = PinState::DriveL;
-                    }
-                    self.tx_bit -= 1;
-                    self.tx_reg >>= 1;
+                    self.tx_time += self.tx_ns_per_bit;
                     if self.tx_bit == 0 {
+                        // Last stop bit complete
                         self.tx_state = UartState::Idle;
+                    } else {
+                        if (self.tx_reg & 1) == 1 {
+                            *self.pin_tx.borrow_mut() = PinState::DriveH;
+                        } else {
+                            *self.pin_tx.borrow_mut() = PinState::DriveL;
+                        }
+                        self.tx_bit -= 1;
+                        self.tx_reg >>= 1;
                     }
                 }
             }
@@ -150,13 +325,88 @@ impl Hardware for SinkUART {
     }
 
     fn event(&mut self, time: u64, event: &str) {
-        if event.eq_ignore_ascii_case("flush") {
-            self.out_close();
-        } else {
-            let byte = u8::from_str_radix(event, 16).unwrap();
-            self.tx(time, byte);
-            if time > 0 {
-                println!("[@{:012X}] UART|{}: Tx 0x{:02X}", time, self.name, byte);
+        // Faults: "mismatch <percent>", "ferr <hex>", "perr <hex>", "glitch <ns>"
+        let mut args = event.split_whitespace();
+        let command = args.next().unwrap_or("").to_ascii_lowercase();
+        let arg = args.next().unwrap_or("");
+        match command.as_str() {
+            "flush" => self.out_close(),
+            "baud" => match arg.parse::<u32>() {
+                Ok(baud) => self.set_baud(baud),
+                Err(_) => println!(
+                    "[@{:012X}] UART|{}: Invalid baud rate {}, event ignored.",
+                    time, self.name, arg
+                ),
+            },
+            "format" => {
+                if !self.set_format(arg) {
+                    println!(
+                        "[@{:012X}] UART|{}: Invalid format {}, event ignored.",
+                        time, self.name, arg
+                    );
+                }
+            }
+            "mismatch" => match arg.parse::<f64>() {
+                Ok(mismatch) => self.mismatch = mismatch,
+                Err(_) => println!(
+                    "[@{:012X}] UART|{}: Invalid baud rate mismatch {}, event ignored.",
+                    time, self.name, arg
+                ),
+            },
+            "break" => {
+                self.send(time, 0x1000, 13); // 13 low bits + stop
+                println!("[@{:012X}] UART|{}: Tx break", time, self.name);
+            }
+            "ferr" | "perr" => {
+                let Ok(data) = u16::from_str_radix(arg, 16) else {
+                    println!(
+                        "[@{:012X}] UART|{}: Invalid data {}, event ignored.",
+                        time, self.name, arg
+                    );
+                    return;
+                };
+                if command.eq("ferr") {
+                    self.tx(time, data, Fault::Framing);
+                } else {
+                    if self.parity.eq(&Parity::None) {
+                        println!(
+                            "[@{:012X}] UART|{}: Parity is disabled, no parity error injected.",
+                            time, self.name
+                        );
+                    }
+                    self.tx(time, data, Fault::Parity);
+                }
+                println!(
+                    "[@{:012X}] UART|{}: Tx 0x{:02X} ({})",
+                    time, self.name, data, command
+                );
+            }
+            "glitch" => {
+                let Ok(duration) = arg.parse::<u64>() else {
+                    println!(
+                        "[@{:012X}] UART|{}: Invalid glitch duration {}, event ignored.",
+                        time, self.name, arg
+                    );
+                    return;
+                };
+                if self.tx_state.eq(&UartState::Idle) {
+                    self.glitch_time = time + duration.max(1);
+                    *self.pin_tx.borrow_mut() = PinState::DriveL;
+                    println!("[@{:012X}] UART|{}: Glitch", time, self.name);
+                }
+            }
+            _ => {
+                let Ok(data) = u16::from_str_radix(event.trim(), 16) else {
+                    println!(
+                        "[@{:012X}] UART|{}: Invalid event {}, event ignored.",
+                        time, self.name, event
+                    );
+                    return;
+                };
+                self.tx(time, data, Fault::None);
+                if time > 0 {
+                    println!("[@{:012X}] UART|{}: Tx 0x{:02X}", time, self.name, data);
+                }
             }
         }
     }
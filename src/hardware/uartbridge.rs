@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::OnceLock;
+use std::thread;
+
+// Original stdout, set aside for the stdio bridge once logging is on stderr
+static STDIO_OUT: OnceLock<File> = OnceLock::new();
+
+// Byte stream connecting an emulated UART host to the outside world
+pub trait UartBridge {
+    fn read(&mut self) -> Option<u8>;
+    fn write(&mut self, byte: u8);
+}
+
+pub struct StdioBridge {
+    rx: Receiver<u8>,
+    out: Box<dyn Write>,
+}
+
+impl StdioBridge {
+    pub fn new() -> Self {
+        // Reads from stdin block, so are moved onto their own thread
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(n @ 1..) = io::stdin().read(&mut buf) {
+                if buf[..n].iter().any(|&byte| tx.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+        StdioBridge {
+            rx,
+            out: match STDIO_OUT.get() {
+                Some(file) => Box::new(file),
+                None => Box::new(io::stdout()),
+            },
+        }
+    }
+}
+
+// Moves all logging to stderr, keeping the original stdout for the bytes
+// bridged by StdioBridge so that they are not interleaved with emulator output
+#[cfg(unix)]
+pub fn log_to_stderr() {
+    use std::os::unix::io::FromRawFd;
+
+    io::stdout().flush().ok();
+    unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd >= 0 {
+            if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) >= 0 {
+                STDIO_OUT.set(File::from_raw_fd(fd)).ok();
+                return;
+            }
+            libc::close(fd);
+        }
+    }
+    println!("[WARNING] Unable to redirect logging to stderr. UART output will be mixed with logging on stdout.");
+}
+
+#[cfg(not(unix))]
+pub fn log_to_stderr() {
+    println!("[WARNING] Logging cannot be redirected on this platform. UART output will be mixed with logging on stdout.");
+}
+
+impl UartBridge for StdioBridge {
+    fn read(&mut self) -> Option<u8> {
+        self.rx.try_recv().ok()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.out.write_all(&[byte]).ok();
+        self.out.flush().ok();
+    }
+}
+
+#[cfg(unix)]
+pub struct PtyBridge {
+    master: std::fs::File,
+    _slave: std::fs::File,
+    pub path: String,
+}
+
+#[cfg(unix)]
+impl PtyBridge {
+    pub fn new() -> io::Result<Self> {
+        use std::ffi::CStr;
+        use std::fs::{File, OpenOptions};
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+            if (libc::grantpt(fd) != 0) | (libc::unlockpt(fd) != 0) {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+            // Holding the slave open keeps the master readable before a terminal connects
+            let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+            }
+
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+
+            Ok(PtyBridge {
+                master,
+                _slave: slave,
+                path,
+            })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl UartBridge for PtyBridge {
+    fn read(&mut self) -> Option<u8> {
+        let mut buf = [0u8; 1];
+        match self.master.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        // Bytes are dropped if nothing is draining the terminal
+        self.master.write_all(&[byte]).ok();
+    }
+}
+
+// Bytes held for a client that is not keeping up before further bytes are dropped
+const TCP_PENDING_MAX: usize = 0x10000;
+
+pub struct TcpBridge {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    pending: VecDeque<u8>,
+    overflow: bool,
+}
+
+impl TcpBridge {
+    pub fn new(port: u16, wait: bool) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let mut bridge = TcpBridge {
+            listener,
+            stream: None,
+            pending: VecDeque::new(),
+            overflow: false,
+        };
+        if wait {
+            println!("[UART] Waiting for a connection on port {}.", port);
+            let (stream, _) = bridge.listener.accept()?;
+            bridge.connect(stream);
+        }
+        bridge.listener.set_nonblocking(true)?;
+        Ok(bridge)
+    }
+
+    fn connect(&mut self, stream: TcpStream) {
+        if let Ok(addr) = stream.peer_addr() {
+            println!("[UART] Client connected from {}.", addr);
+        }
+        stream.set_nonblocking(true).ok();
+        stream.set_nodelay(true).ok();
+        self.stream = Some(stream);
+    }
+
+    fn disconnect(&mut self) {
+        println!("[UART] Client disconnected.");
+        self.stream = None;
+        self.pending.clear();
+        self.overflow = false;
+    }
+
+    fn poll(&mut self) {
+        // Accept a new client if the previous one has gone
+        if self.stream.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                self.connect(stream);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        // Sends as much as the socket will take, the rest is retried later
+        while let Some(stream) = &mut self.stream {
+            let (bytes, _) = self.pending.as_slices();
+            if bytes.is_empty() {
+                self.overflow = false;
+                break;
+            }
+            match stream.write(bytes) {
+                Ok(0) => self.disconnect(),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => self.disconnect(),
+            }
+        }
+    }
+}
+
+impl UartBridge for TcpBridge {
+    fn read(&mut self) -> Option<u8> {
+        self.poll();
+        self.flush();
+        let stream = self.stream.as_mut()?;
+        let mut buf = [0u8; 1];
+        match stream.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+            _ => {
+                self.disconnect();
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        // Bytes are dropped while no client is connected
+        if self.stream.is_none() {
+            return;
+        }
+        if self.pending.len() < TCP_PENDING_MAX {
+            self.pending.push_back(byte);
+        } else if !self.overflow {
+            println!("[WARNING] UART client is not reading from the TCP connection. Bytes will be dropped until it catches up.");
+            self.overflow = true;
+        }
+        self.flush();
+    }
+}
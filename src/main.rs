@@ -1,4 +1,7 @@
 use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use clap::Parser;
 
@@ -11,15 +14,27 @@ mod memory;
 mod nets;
 mod peripherals;
 
-use crate::boards::quty::QUTy;
+use crate::boards::breakout::Breakout;
+use crate::boards::described::DescribedBoard;
+use crate::boards::description::BoardDescription;
+use crate::boards::Board;
+use crate::devices::DeviceType;
 use crate::events::Event;
+use crate::hardware::{sinkuart, uartbridge};
 
 use lazy_static::lazy_static;
 
+#[cfg(not(test))]
 lazy_static! {
     pub static ref CLI: Cli = Cli::parse();
 }
 
+// Unit tests use the default arguments
+#[cfg(test)]
+lazy_static! {
+    pub static ref CLI: Cli = Cli::parse_from(["avremu", "test.hex"]);
+}
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
@@ -30,6 +45,14 @@ pub struct Cli {
     #[arg(short, long)]
     events: Option<String>,
 
+    /// Specify board: quty (default), breakout, or a board description file in .TOML format
+    #[arg(short, long)]
+    board: Option<String>,
+
+    /// Specify microcontroller for the breakout board, e.g. ATtiny3227 (default ATtiny1626)
+    #[arg(long, value_name = "DEVICE")]
+    mcu: Option<String>,
+
     /// Specify emulation runtime limit in nanoseconds
     #[arg(short, long)]
     timeout: Option<u64>,
@@ -54,12 +77,68 @@ pub struct Cli {
     #[arg(short = 'u', long)]
     net_undef: bool,
 
+    /// Terminate with a non-zero exit status if two drivers contend on a net
+    #[arg(long)]
+    fail_on_contention: bool,
+
+    /// Specify die temperature in degrees Celsius for the ADC temperature sensor
+    #[arg(long, default_value_t = 25.0, allow_negative_numbers = true)]
+    temperature: f32,
+
+    /// Specify RMS noise in LSB added to ADC conversions
+    #[arg(long, default_value_t = 0.0)]
+    adc_noise: f32,
+
+    /// Specify ADC offset error in LSB
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    adc_offset: f32,
+
+    /// Specify ADC gain error in percent
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    adc_gain_error: f32,
+
+    /// Specify ADC integral non-linearity in LSB (peak, at mid-scale)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    adc_inl: f32,
+
+    /// Specify seed for the ADC noise model
+    #[arg(long, default_value_t = 0)]
+    adc_seed: u64,
+
+    /// Specify baud rate of the emulated UART host
+    #[arg(long, default_value_t = 9600)]
+    uart_baud: u32,
+
+    /// Specify frame format of the emulated UART host, e.g. 8N1 or 7E2
+    #[arg(long, default_value = "8N1", value_parser = sinkuart::validate_format)]
+    uart_format: String,
+
+    /// Bridge the emulated UART host to a pseudo-terminal, paced to wall-clock time
+    #[arg(long, conflicts_with = "uart_stdio")]
+    uart_pty: bool,
+
+    /// Bridge the emulated UART host to stdin/stdout, paced to wall-clock time (logging moves to stderr)
+    #[arg(long, conflicts_with = "uart_tcp")]
+    uart_stdio: bool,
+
+    /// Serve the emulated UART host on a local TCP port, paced to wall-clock time
+    #[arg(long, value_name = "PORT", conflicts_with = "uart_pty")]
+    uart_tcp: Option<u16>,
+
+    /// Block emulation until a client connects to the UART TCP port
+    #[arg(long, requires = "uart_tcp")]
+    uart_tcp_wait: bool,
+
     /// Enable debug output
     #[arg(short, long)]
     debug: bool,
 }
 
 fn main() {
+    if CLI.uart_stdio {
+        uartbridge::log_to_stderr();
+    }
+
     let firmware = &CLI.firmware;
 
     if Path::new(firmware).exists() {
@@ -89,21 +168,53 @@ fn main() {
         }
     };
 
-    let mut quty = QUTy::new();
-    quty.events(events);
-    quty.mcu_programme(firmware);
+    let board: Result<Box<dyn Board>, String> = match CLI.board.as_deref() {
+        None | Some("quty") => {
+            DescribedBoard::new(&BoardDescription::quty()).map(|b| Box::new(b) as _)
+        }
+        Some("breakout") => match CLI.mcu.as_deref() {
+            None => Ok(DeviceType::ATtiny1626),
+            Some(name) => {
+                DeviceType::from_name(name).ok_or(format!("Unsupported microcontroller {}.", name))
+            }
+        }
+        .map(|dt| Box::new(Breakout::new(dt)) as _),
+        Some(filename) => BoardDescription::from_file(filename).and_then(|description| {
+            println!("[BOARD] {}: Loaded {}.", filename, description.name);
+            DescribedBoard::new(&description)
+                .map(|b| Box::new(b) as _)
+                .map_err(|error| format!("{}: {}", description.name, error))
+        }),
+    };
+    let mut board = match board {
+        Ok(board) => board,
+        Err(error) => {
+            println!("[BOARD] {}", error);
+            return;
+        }
+    };
+    board.events(events);
+    board.mcu_programme(firmware);
 
     if CLI.debug {
-        quty.core_debug();
+        board.core_debug();
     }
 
+    // Interactive UART sessions need emulation paced to wall-clock time
+    let realtime = CLI.uart_pty | CLI.uart_stdio | CLI.uart_tcp.is_some();
+    let start = Instant::now();
+    let mut time_sync = 0u64;
+
     let mut time = 0u64;
     let mut time_step;
     loop {
-        time_step = quty.step();
+        time_step = board.step();
 
         // Board returns a step time of 0 to indicate termination
         if time_step == 0 {
+            if board.contention() {
+                println!("[END] Net contention detected.");
+            }
             break;
         }
 
@@ -114,19 +225,31 @@ fn main() {
             println!("[END] Time limit elapsed.");
             break;
         }
+
+        if realtime && (time >= time_sync) {
+            time_sync = time + 1_000_000; // 1 ms
+            let elapsed = start.elapsed().as_nanos() as u64;
+            if time > elapsed {
+                thread::sleep(Duration::from_nanos(time - elapsed));
+            }
+        }
     }
 
     println!("[INFO] Programme terminated after {} ns.", time);
 
     if CLI.dump_stack {
-        quty.mcu_dumpstack();
+        board.mcu_dumpstack();
     }
 
     if CLI.dump_regs {
-        quty.core_dumpregs();
+        board.core_dumpregs();
     }
 
     if CLI.dump_stdout {
-        quty.mcu_write_stdout();
+        board.mcu_write_stdout();
+    }
+
+    if board.contention() {
+        process::exit(1);
     }
 }
@@ -3,16 +3,46 @@ use std::rc::Weak;
 
 use crate::CLI;
 
-#[derive(Debug)]
+// Supply and input thresholds (VIL max 0.3 VDD, VIH min 0.7 VDD)
+pub const VDD: f32 = 3.3;
+pub const VIL: f32 = 0.3 * VDD;
+pub const VIH: f32 = 0.7 * VDD;
+
+// Source impedances in ohms
+const R_DRIVE: f32 = 25.0;
+const R_PULL: f32 = 35000.0;
+
+#[derive(Debug, Clone, Copy)]
 pub enum PinState {
     Open,
     WeakPullDown,
     WeakPullUp,
-    UndefinedWeak,
     DriveL,
     DriveH,
     DriveAnalog(f32),
-    UndefinedStrong,
+    Resistive(f32, f32), // source voltage, resistance in ohms
+}
+
+impl PinState {
+    fn source(&self) -> Option<(f32, f32)> {
+        // Thevenin equivalent (voltage, resistance) of the pin
+        match *self {
+            PinState::Open => None,
+            PinState::WeakPullDown => Some((0.0, R_PULL)),
+            PinState::WeakPullUp => Some((VDD, R_PULL)),
+            PinState::DriveL => Some((0.0, R_DRIVE)),
+            PinState::DriveH => Some((VDD, R_DRIVE)),
+            PinState::DriveAnalog(v) => Some((v, R_DRIVE)),
+            PinState::Resistive(v, r) => Some((v, r.max(f32::MIN_POSITIVE))),
+        }
+    }
+
+    fn driven(&self) -> bool {
+        matches!(
+            self,
+            PinState::DriveL | PinState::DriveH | PinState::DriveAnalog(_)
+        )
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -25,7 +55,11 @@ pub enum NetState {
 
 pub struct Net {
     pub state: NetState,
-    io: Vec<Weak<RefCell<PinState>>>,
+    pub voltage: f32,
+    pub contention: bool,
+    pub floating: bool,                         // no pin sources or sinks current
+    pub resistance: f32,                        // Thevenin resistance in ohms, infinite if floating
+    io: Vec<(Weak<RefCell<PinState>>, String)>, // pin and owner
     name: String,
 }
 
@@ -33,81 +67,73 @@ impl Net {
     pub fn new(name: String) -> Self {
         Net {
             state: NetState::Undefined,
+            voltage: 0.0,
+            contention: false,
+            floating: true,
+            resistance: f32::INFINITY,
             io: Vec::new(),
             name,
         }
     }
 
-    pub fn connect(&mut self, pin: Weak<RefCell<PinState>>) {
-        self.io.push(pin);
+    pub fn connect(&mut self, pin: Weak<RefCell<PinState>>, owner: String) {
+        self.io.push((pin, owner));
     }
 
     pub fn update(&mut self, time: u64) {
-        let mut dps = PinState::Open;
+        // Solve the net voltage as the conductance weighted mean of all sources
+        let mut current = 0.0;
+        let mut conductance = 0.0;
+        let mut analog = false;
+        let mut drivers = Vec::new();
 
-        for ps in &self.io {
-            match *ps.upgrade().unwrap().borrow() {
-                PinState::Open => {}
-                PinState::WeakPullDown => {
-                    match dps {
-                        PinState::Open => dps = PinState::WeakPullDown,
-                        PinState::WeakPullUp => dps = PinState::UndefinedWeak,
-                        _ => {} // if driven, already pulled down, or undefined, state unchanged
-                    }
+        for (ps, owner) in &self.io {
+            let ps = *ps.upgrade().unwrap().borrow();
+            if let Some((v, r)) = ps.source() {
+                current += v / r;
+                conductance += 1.0 / r;
+                if ps.driven() {
+                    drivers.push((v, ps, owner));
                 }
-                PinState::WeakPullUp => {
-                    match dps {
-                        PinState::Open => dps = PinState::WeakPullUp,
-                        PinState::WeakPullDown => dps = PinState::UndefinedWeak,
-                        _ => {} // if driven, already pulled up, or undefined, state unchanged
-                    }
-                }
-                PinState::UndefinedWeak => {
-                    match dps {
-                        PinState::DriveH => dps = PinState::DriveH,
-                        PinState::DriveL => dps = PinState::DriveL,
-                        PinState::DriveAnalog(v) => dps = PinState::DriveAnalog(v),
-                        _ => {} // unless driven, remains weakly undefined
-                    }
-                }
-                PinState::DriveL => {
-                    match dps {
-                        PinState::DriveH | PinState::DriveAnalog(_) => {
-                            dps = PinState::UndefinedStrong;
-                            break;
-                        } // The net cant get out of this state
-                        _ => dps = PinState::DriveL,
-                    }
-                }
-                PinState::DriveH => {
-                    match dps {
-                        PinState::DriveL | PinState::DriveAnalog(_) => {
-                            dps = PinState::UndefinedStrong;
-                            break;
-                        } // The net cant get out of this state
-                        _ => dps = PinState::DriveH,
-                    }
-                }
-                PinState::DriveAnalog(v) => {
-                    match dps {
-                        PinState::DriveL | PinState::DriveH | PinState::DriveAnalog(_) => {
-                            dps = PinState::UndefinedStrong;
-                            break;
-                        } // The net cant get out of this state
-                        _ => dps = PinState::DriveAnalog(v),
-                    }
-                }
-                PinState::UndefinedStrong => break, // We shouldn't ever get here
             }
+            analog |= matches!(ps, PinState::DriveAnalog(_));
+        }
+
+        // Contention is any two drivers more than a logic threshold apart
+        let (min, max) = drivers.iter().fold((VDD, 0.0f32), |(min, max), (v, _, _)| {
+            (min.min(*v), max.max(*v))
+        });
+        let contention = max - min > VIL;
+        if contention & !self.contention {
+            let owners: Vec<String> = drivers
+                .iter()
+                .map(|(_, ps, owner)| format!("{} ({:?})", owner, ps))
+                .collect();
+            println!(
+                "[@{:012X}] NET|{}: Contention between {}",
+                time,
+                self.name,
+                owners.join(", ")
+            );
         }
+        self.contention = contention;
 
-        let state_new = match dps {
-            PinState::Open | PinState::UndefinedWeak | PinState::UndefinedStrong => {
+        self.floating = conductance == 0.0;
+        self.resistance = 1.0 / conductance;
+        let state_new = if self.floating {
+            self.voltage = 0.0;
+            NetState::Undefined
+        } else {
+            self.voltage = current / conductance;
+            if analog {
+                NetState::Analog(self.voltage)
+            } else if self.voltage >= VIH {
+                NetState::High
+            } else if self.voltage <= VIL {
+                NetState::Low
+            } else {
                 NetState::Undefined
             }
-            PinState::WeakPullDown | PinState::DriveL => NetState::Low,
-            PinState::WeakPullUp | PinState::DriveH => NetState::High,
-            PinState::DriveAnalog(v) => NetState::Analog(v),
         };
 
         if self.state != state_new
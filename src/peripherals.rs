@@ -1,25 +1,41 @@
 pub mod adc;
 pub mod clkctrl;
+pub mod clkpr;
 pub mod cpu;
 pub mod cpuint;
+pub mod evsys;
+pub mod exint;
 pub mod port;
 pub mod portmux;
+pub mod slpctrl;
+pub mod smcr;
 pub mod spi;
 pub mod stdio;
 pub mod tca;
 pub mod tcb;
 pub mod usart;
 
+use slpctrl::SleepMode;
+
 pub trait InterruptSource {
     fn interrupt(&mut self, _mask: u8) -> bool {
         // This function should return the bitwise and of the
         // corresponding intflag and intctrl registers of the peripheral
         false
     }
+
+    // Called when the interrupt is serviced, for flags cleared in hardware
+    fn acknowledge(&mut self, _mask: u8) {}
 }
 
 pub trait Clocked {
     fn tick(&mut self, _time: u64) {}
+    // Called when the device enters or leaves (None) a sleep mode, returns
+    // whether the peripheral is still clocked. CLK_PER stops in STANDBY and
+    // POWERDOWN unless a peripheral is set to run in standby.
+    fn sleep(&mut self, mode: Option<SleepMode>) -> bool {
+        !matches!(mode, Some(SleepMode::Standby | SleepMode::PowerDown))
+    }
 }
 
 pub trait Ccp {
@@ -29,3 +45,24 @@ pub trait Ccp {
 pub trait ClockSource {
     fn clock_period(&self) -> u64;
 }
+
+pub trait EventGenerator {
+    fn event_state(&self, _id: u8) -> bool {
+        // This function should return the current level of the event output
+        // selected by id; pulse events are held for a single clock cycle
+        false
+    }
+}
+
+pub trait EventUser {
+    fn event(&mut self, _id: u8, _state: bool) {}
+    // Called by EVSYS when the USER register is written, connected is false
+    // when the user is returned to OFF
+    fn connect(&mut self, _id: u8, _connected: bool) {}
+}
+
+pub trait PinMux {
+    // Called by PORTMUX when the routing field for the peripheral is changed;
+    // the peripheral should relinquish any overrides on the previous pins
+    fn route(&mut self, _route: u8) {}
+}
@@ -2,9 +2,12 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::memory::MemoryMapped;
-use crate::nets::NetState;
+use crate::peripherals::slpctrl::SleepMode;
+use crate::peripherals::ClockSource;
 use crate::peripherals::Clocked;
 use crate::peripherals::InterruptSource;
+use crate::peripherals::{EventGenerator, EventUser};
+use crate::CLI;
 
 use super::port::Port;
 
@@ -36,16 +39,55 @@ const ADC_WINLTH: usize = 0x1D;
 const ADC_WINHTL: usize = 0x1E;
 const ADC_WINHTH: usize = 0x1F;
 
+const ADC_RESRDY: u8 = 0x01;
+const ADC_SAMPRDY: u8 = 0x02;
+const ADC_WCMP: u8 = 0x04;
+const ADC_RESOVR: u8 = 0x08;
+const ADC_SAMPOVR: u8 = 0x10;
+const ADC_TRIGOVR: u8 = 0x20;
+
+const ADC_GEN_RESRDY: u8 = 0;
+const ADC_GEN_SAMPRDY: u8 = 1;
+const ADC_GEN_WCMP: u8 = 2;
+
+const ADC_MUX_GND: u8 = 0x30;
+const ADC_MUX_VDDDIV10: u8 = 0x31;
+const ADC_MUX_TEMPSENSE: u8 = 0x32;
+const ADC_MUX_DACREF0: u8 = 0x33;
+
+const ADC_VDD: f32 = 3.3;
+
+// AC0.DACREF, AC0 is otherwise unimplemented
+const AC_DACREF: usize = 0x04;
+
+// Temperature sensor calibration, mapped into SIGROW as TEMPSENSE0 (slope)
+// and TEMPSENSE1 (offset) for a 12-bit conversion against the 1.024 V
+// reference, such that T[K] = ((TEMPSENSE1 - RESULT) * TEMPSENSE0) >> 12
+pub const ADC_TEMPSENSE0: u16 = 0x0400;
+pub const ADC_TEMPSENSE1: u16 = 0x0A84;
+
 #[allow(non_camel_case_types)]
 #[derive(PartialEq)]
 #[allow(clippy::upper_case_acronyms)]
 enum ADC_MODE {
     SINGLE_8BIT,
     SINGLE_12BIT,
-    _SERIES,
-    _SERIES_SCALING,
-    _BURST,
-    _BURST_SCALING,
+    SERIES,
+    SERIES_SCALING,
+    BURST,
+    BURST_SCALING,
+    RESERVED,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+enum ADC_START {
+    STOP,
+    IMMEDIATE,
+    MUXPOS,
+    MUXNEG,
+    EVENT,
     RESERVED,
 }
 
@@ -61,6 +103,78 @@ enum ADC_REFSEL {
     RESERVED,
 }
 
+// Approximate sampling capacitance, in farads
+const ADC_CSAMPLE: f32 = 8e-12;
+
+// Non-ideal analog front end applied to each sample. Errors and noise are
+// taken from the CLI and are ideal unless any are specified, the source
+// impedance of each AINn input is that of the net it is connected to.
+struct AnalogModel {
+    noise: f32,      // RMS, in LSB
+    offset: f32,     // In LSB
+    gain_error: f32, // Fractional
+    inl: f32,        // Peak deviation at mid-scale, in LSB
+    vcap: [f32; 2],  // Residual voltage on positive/negative sampling capacitors
+    rng: u64,
+}
+
+impl AnalogModel {
+    fn new() -> Self {
+        AnalogModel {
+            noise: CLI.adc_noise,
+            offset: CLI.adc_offset,
+            gain_error: CLI.adc_gain_error / 100.0,
+            inl: CLI.adc_inl,
+            vcap: [0.0; 2],
+            rng: CLI.adc_seed,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // SplitMix64, deterministic for a given seed
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn uniform(&mut self) -> f32 {
+        // (0, 1]
+        ((self.next_u64() >> 40) + 1) as f32 / (1u64 << 24) as f32
+    }
+
+    fn gaussian(&mut self) -> f32 {
+        // Box-Muller transform
+        let u1 = self.uniform();
+        let u2 = self.uniform();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+    }
+
+    fn settle(&mut self, cap: usize, (voltage, resistance): (f32, f32), t_sample: f32) -> f32 {
+        // The sampling capacitor charges from its residual voltage through
+        // the source resistance for the sampling period, and holds its
+        // residual voltage if the input is floating
+        if resistance > 0.0 {
+            let tau = resistance * ADC_CSAMPLE;
+            self.vcap[cap] += (voltage - self.vcap[cap]) * (1.0 - (-t_sample / tau).exp());
+        } else {
+            self.vcap[cap] = voltage;
+        }
+        self.vcap[cap]
+    }
+
+    fn apply(&mut self, code: f32, min: f32, max: f32) -> f32 {
+        let x = (code - min) / (max - min);
+        let mut code = code * (1.0 + self.gain_error) + self.offset;
+        code += self.inl * 4.0 * x * (1.0 - x);
+        if self.noise > 0.0 {
+            code += self.noise * self.gaussian();
+        }
+        code
+    }
+}
+
 #[allow(dead_code)]
 pub struct Adc {
     name: String,
@@ -68,70 +182,313 @@ pub struct Adc {
     enabled: bool,
     presc: u8,
     mode: ADC_MODE,
+    start: ADC_START,
     vref: ADC_REFSEL,
     clk_divider: u8,
+    clock_source: Rc<RefCell<dyn ClockSource>>,
     ports: [Rc<RefCell<Port>>; 3],
     ain: [(usize, u8); 15],
-    muxpos: usize,
+    ac: Rc<RefCell<dyn MemoryMapped>>,
+    temperature: f32,
+    model: AnalogModel,
     busy: bool,
     delay: usize,
-    sample: u16,
+    sample: i32,
+    accum: i32,
+    accum_count: u16,
+    ev_start_state: bool,
+    ev_out: u8,
 }
 
 impl Adc {
-    pub fn new(name: String, ports: [Rc<RefCell<Port>>; 3], ain: [(usize, u8); 15]) -> Self {
+    pub fn new(
+        name: String,
+        clock_source: Rc<RefCell<dyn ClockSource>>,
+        ports: [Rc<RefCell<Port>>; 3],
+        ain: [(usize, u8); 15],
+        ac: Rc<RefCell<dyn MemoryMapped>>,
+    ) -> Self {
         Adc {
             name,
             regs: [0; 0x20],
             enabled: false,
-            presc: 2,
+            presc: 1,
             mode: ADC_MODE::SINGLE_8BIT,
+            start: ADC_START::STOP,
             vref: ADC_REFSEL::VDD,
             clk_divider: 0,
+            clock_source,
             ports,
             ain,
-            muxpos: 0,
+            ac,
+            temperature: CLI.temperature,
+            model: AnalogModel::new(),
             busy: false,
             delay: 0,
             sample: 0,
+            accum: 0,
+            accum_count: 0,
+            ev_start_state: false,
+            ev_out: 0,
+        }
+    }
+
+    fn sampnum(&self) -> u16 {
+        match self.regs[ADC_CTRLF] & 0x0F {
+            n @ 0x0..=0xA => 1 << n,
+            _ => 1,
         }
     }
 
+    fn is_leftadj(&self) -> bool {
+        (self.regs[ADC_CTRLF] & 0x10) != 0
+    }
+
+    fn is_freerun(&self) -> bool {
+        (self.regs[ADC_CTRLF] & 0x20) != 0
+    }
+
+    fn is_diff(&self) -> bool {
+        (self.regs[ADC_COMMAND] & 0x80) != 0
+    }
+
+    fn gain(&self) -> f32 {
+        // VIA field is common to MUXPOS and MUXNEG
+        if (self.regs[ADC_MUXPOS] & 0xC0) == 0x40 {
+            match self.regs[ADC_PGACTRL] >> 5 {
+                n @ 0x0..=0x4 => f32::from(1u8 << n),
+                _ => 1.0,
+            }
+        } else {
+            1.0
+        }
+    }
+
+    fn input(&self, mux: u8) -> (f32, f32) {
+        // Thevenin equivalent (voltage, resistance) of the selected input,
+        // the internal inputs are ideal sources
+        let voltage = match mux {
+            0x01..=0x0F => {
+                let (portidx, pinidx) = self.ain[usize::from(mux) - 1];
+                let port = self.ports[portidx].borrow();
+                return (port.get_netvoltage(pinidx), port.get_netresistance(pinidx));
+            }
+            ADC_MUX_VDDDIV10 => ADC_VDD / 10.0,
+            ADC_MUX_TEMPSENSE => {
+                let kelvin = self.temperature + 273.15;
+                let result =
+                    f32::from(ADC_TEMPSENSE1) - kelvin * 4096.0 / f32::from(ADC_TEMPSENSE0);
+                result * 1.024 / 4096.0
+            }
+            ADC_MUX_DACREF0 => {
+                // VREF.ACREF is not implemented, so DACREF is
+                // scaled from the default 1.024 V reference
+                let dacref = self.ac.borrow_mut().read(AC_DACREF).0;
+                f32::from(dacref) * 1.024 / 256.0
+            }
+            _ => 0.0, // AIN0 is not bonded out (UPDI), GND
+        };
+        (voltage, 0.0)
+    }
+
+    fn t_sample(&self) -> f32 {
+        // Sampling period of SAMPDUR + 2 CLK_ADC cycles, in seconds
+        let clk_adc = (u64::from(self.presc) + 1) * self.clock_source.borrow().clock_period();
+        ((u64::from(self.regs[ADC_CTRLE]) + 2) * clk_adc) as f32 * 1e-9
+    }
+
+    fn convert(&mut self) -> i32 {
+        // Returns a 12-bit conversion of the selected input(s),
+        // signed if a differential conversion is selected
+        let vref = match self.vref {
+            ADC_REFSEL::VDD => ADC_VDD,
+            ADC_REFSEL::V1024 => 1.024,
+            ADC_REFSEL::V2048 => 2.048,
+            ADC_REFSEL::V2500 => 2.5,
+            ADC_REFSEL::V4096 => 2.8,
+            _ => 0.001,
+        };
+        let t_sample = self.t_sample();
+        let muxpos = self.regs[ADC_MUXPOS] & 0x3F;
+        let voltage = self.model.settle(0, self.input(muxpos), t_sample);
+        if self.is_diff() {
+            let muxneg = self.regs[ADC_MUXNEG] & 0x3F;
+            let negative = self.model.settle(1, self.input(muxneg), t_sample);
+            let code = 2048.0 * self.gain() * (voltage - negative) / vref;
+            let code = self.model.apply(code, -2048.0, 2048.0);
+            code.clamp(-2048.0, 2047.0) as i32
+        } else {
+            let code = 4096.0 * self.gain() * voltage / vref;
+            let code = self.model.apply(code, 0.0, 4096.0);
+            code.clamp(0.0, 4095.0) as i32
+        }
+    }
+
+    fn window(&mut self, value: i32) {
+        // Only the 16 LSBs of the source are compared
+        let (value, lt, ht) = if self.is_diff() {
+            (
+                i32::from(value as i16),
+                i32::from(i16::from_le_bytes([
+                    self.regs[ADC_WINLTL],
+                    self.regs[ADC_WINLTH],
+                ])),
+                i32::from(i16::from_le_bytes([
+                    self.regs[ADC_WINHTL],
+                    self.regs[ADC_WINHTH],
+                ])),
+            )
+        } else {
+            (
+                i32::from(value as u16),
+                i32::from(u16::from_le_bytes([
+                    self.regs[ADC_WINLTL],
+                    self.regs[ADC_WINLTH],
+                ])),
+                i32::from(u16::from_le_bytes([
+                    self.regs[ADC_WINHTL],
+                    self.regs[ADC_WINHTH],
+                ])),
+            )
+        };
+        let matched = match self.regs[ADC_CTRLD] & 0x07 {
+            0x1 => value < lt,                  // BELOW
+            0x2 => value > ht,                  // ABOVE
+            0x3 => (value > lt) & (value < ht), // INSIDE
+            0x4 => (value < lt) | (value > ht), // OUTSIDE
+            _ => false,                         // NONE
+        };
+        if matched {
+            self.regs[ADC_INTFLAGS] |= ADC_WCMP;
+            self.ev_out |= 1 << ADC_GEN_WCMP;
+        }
+    }
+
+    fn trigger(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.busy {
+            self.regs[ADC_INTFLAGS] |= ADC_TRIGOVR;
+            return;
+        }
+        if matches!(self.mode, ADC_MODE::BURST | ADC_MODE::BURST_SCALING) {
+            // A burst always starts a fresh accumulation
+            self.accum = 0;
+            self.accum_count = 0;
+        }
+        self.sample();
+    }
+
     fn sample(&mut self) {
         if !self.busy {
             self.busy = true;
+            // Sampling takes SAMPDUR + 2 CLK_ADC cycles, followed by
+            // the conversion itself
             self.delay = self.regs[ADC_CTRLE] as usize
+                + 2
                 + if self.mode.eq(&ADC_MODE::SINGLE_8BIT) {
                     9
                 } else {
                     13
                 };
-            let ainp = self.muxpos;
-            self.sample = if self.muxpos == 0 {
-                0
-            } else {
-                let (portidx, pinidx) = self.ain[ainp - 1];
-                let vref = match self.vref {
-                    ADC_REFSEL::VDD => 3.3,
-                    ADC_REFSEL::V1024 => 1.024,
-                    ADC_REFSEL::V2048 => 2.048,
-                    ADC_REFSEL::V2500 => 2.5,
-                    ADC_REFSEL::V4096 => 2.8,
-                    _ => 0.001,
-                };
-                match self.ports[portidx].borrow_mut().get_netstate(pinidx) {
-                    NetState::High => 0x0FFF,
-                    NetState::Low => 0x0000,
-                    NetState::Undefined => 0x0000,
-                    NetState::Analog(voltage) => {
-                        let mut conversion = 4096.0 * voltage / vref;
-                        if conversion > 4095.0 {
-                            conversion = 4095.0
-                        };
-                        conversion as u16
-                    }
+            self.sample = self.convert();
+        }
+    }
+
+    fn set_result(&mut self, result: i32) {
+        if (self.regs[ADC_INTFLAGS] & ADC_RESRDY) != 0 {
+            self.regs[ADC_INTFLAGS] |= ADC_RESOVR;
+        }
+        // Results are sign extended to the full 32 bits
+        let bytes = result.to_le_bytes();
+        self.regs[ADC_RESULT0..=ADC_RESULT3].copy_from_slice(&bytes);
+        self.regs[ADC_INTFLAGS] |= ADC_RESRDY;
+        self.ev_out |= 1 << ADC_GEN_RESRDY;
+        if (self.regs[ADC_CTRLD] & 0x08) == 0 {
+            self.window(result);
+        }
+    }
+
+    fn set_sample(&mut self, sample: i32) {
+        if (self.regs[ADC_INTFLAGS] & ADC_SAMPRDY) != 0 {
+            self.regs[ADC_INTFLAGS] |= ADC_SAMPOVR;
+        }
+        let bytes = (sample as i16).to_le_bytes();
+        self.regs[ADC_SAMPLEL] = bytes[0];
+        self.regs[ADC_SAMPLEH] = bytes[1];
+        self.regs[ADC_INTFLAGS] |= ADC_SAMPRDY;
+        self.ev_out |= 1 << ADC_GEN_SAMPRDY;
+        if (self.regs[ADC_CTRLD] & 0x08) != 0 {
+            self.window(sample);
+        }
+    }
+
+    fn scale(&self, accum: i32) -> i32 {
+        // Scaling modes adjust the accumulated result to 16 bits
+        let n = self.sampnum().trailing_zeros() as i32;
+        if n > 4 {
+            accum >> (n - 4)
+        } else {
+            accum << (4 - n)
+        }
+    }
+
+    fn complete(&mut self) {
+        self.busy = false;
+
+        let sample = if self.mode.eq(&ADC_MODE::SINGLE_8BIT) {
+            self.sample >> 4
+        } else {
+            self.sample
+        };
+        self.set_sample(sample);
+
+        match self.mode {
+            ADC_MODE::SINGLE_8BIT => {
+                self.set_result(if self.is_leftadj() {
+                    sample << 8
+                } else {
+                    sample
+                });
+            }
+            ADC_MODE::SINGLE_12BIT => {
+                self.set_result(if self.is_leftadj() {
+                    sample << 4
+                } else {
+                    sample
+                });
+            }
+            ADC_MODE::SERIES
+            | ADC_MODE::SERIES_SCALING
+            | ADC_MODE::BURST
+            | ADC_MODE::BURST_SCALING => {
+                self.accum += sample;
+                self.accum_count += 1;
+                if self.accum_count >= self.sampnum() {
+                    let result = match self.mode {
+                        ADC_MODE::SERIES_SCALING | ADC_MODE::BURST_SCALING => {
+                            self.scale(self.accum)
+                        }
+                        _ => self.accum,
+                    };
+                    self.set_result(result);
+                    self.accum = 0;
+                    self.accum_count = 0;
+                } else if matches!(self.mode, ADC_MODE::BURST | ADC_MODE::BURST_SCALING) {
+                    // Burst continues without a further trigger
+                    self.sample();
+                    return;
                 }
             }
+            _ => {} // No other modes implemented
+        }
+
+        if self.is_freerun() {
+            self.trigger();
+        } else if self.start.eq(&ADC_START::IMMEDIATE) {
+            self.start = ADC_START::STOP;
+            self.regs[ADC_COMMAND] &= 0xF8; // STOP
         }
     }
 }
@@ -146,13 +503,7 @@ impl MemoryMapped for Adc {
             ADC_CTRLA..=ADC_INTFLAGS | ADC_DBGCTRL..=ADC_MUXNEG | ADC_TEMP0..=ADC_TEMP2 => {
                 (self.regs[address], 0)
             }
-            ADC_STATUS => {
-                if self.busy {
-                    (1, 0)
-                } else {
-                    (0, 0)
-                }
-            }
+            ADC_STATUS => (u8::from(self.busy), 0),
             ADC_RESULT0 => {
                 self.regs[ADC_TEMP0] = self.regs[ADC_RESULT1];
                 self.regs[ADC_TEMP1] = self.regs[ADC_RESULT2];
@@ -186,17 +537,23 @@ impl MemoryMapped for Adc {
             ADC_CTRLA => {
                 self.regs[ADC_CTRLA] = value;
                 self.enabled = (value & 0x01) != 0;
-                if value & 0xFE != 0 {
-                    println!("[WARNING] RUNSTDBY/LOWLAT features are not implemented for ADC in this emulator. These bits will be ignored.");
+                if !self.enabled {
+                    self.busy = false;
+                    self.accum = 0;
+                    self.accum_count = 0;
+                }
+                if value & 0x7E != 0 {
+                    println!("[WARNING] LOWLAT feature is not implemented for ADC in this emulator. This bit will be ignored.");
                 }
             }
             ADC_CTRLB => {
                 self.regs[ADC_CTRLB] = value;
-                self.presc = match value & 0x0F {
-                    0x0..=0x7 => (self.presc << 1) + 1,
-                    0x8..=0xB => (self.presc << 2) + 3,
-                    0xC..=0xF => ((self.presc - 12) << 3) + 39,
-                    _ => 1,
+                // Stored as the divider less one, i.e. CLK_PER cycles skipped
+                let presc = value & 0x0F;
+                self.presc = match presc {
+                    0x0..=0x7 => (presc << 1) + 1,
+                    0x8..=0xB => ((presc - 8) << 2) + 19,
+                    _ => ((presc - 12) << 3) + 39,
                 }
             }
             ADC_CTRLC => {
@@ -210,24 +567,23 @@ impl MemoryMapped for Adc {
                     0x7 => ADC_REFSEL::V4096,
                     _ => ADC_REFSEL::RESERVED,
                 };
-                if (value >> 3) < 4 {
-                    // TODO: Fix hardcoding for 3.3 MHz
+                // TIMEBASE should be the number of CLK_PER cycles in 1 us
+                let timebase = 1000u64.div_ceil(self.clock_source.borrow().clock_period());
+                if u64::from(value >> 3) < timebase.min(31) {
                     println!(
-                        "[WARNING] Inappropriate TIMEBASE value specified for ADC: {}.",
-                        value >> 3
+                        "[WARNING] Inappropriate TIMEBASE value specified for ADC: {} (expected {} for current CLK_PER).",
+                        value >> 3,
+                        timebase
                     );
                 }
             }
             ADC_CTRLD => {
-                println!("[WARNING] CTRLD features are not implemented for ADC in this emulator. This register will be ignored.");
                 self.regs[ADC_CTRLD] = value;
-            }
-            ADC_INTCTRL => {
-                if value > 1 {
-                    println!("[WARNING] Only RESRDY is implemented for ADC in this emulator. No other interrupt flags will be set.");
+                if value & 0x07 > 0x04 {
+                    println!("[WARNING] Invalid WINCM specified for ADC. The window comparator will be disabled.");
                 }
-                self.regs[ADC_INTCTRL] = value;
             }
+            ADC_INTCTRL => self.regs[ADC_INTCTRL] = value,
             ADC_INTFLAGS => self.regs[ADC_INTFLAGS] &= !value,
             ADC_DBGCTRL => {
                 println!("[WARNING] DBGCTRL features are not implemented for ADC in this emulator. This register will be ignored.");
@@ -238,42 +594,67 @@ impl MemoryMapped for Adc {
             }
             ADC_CTRLF => {
                 self.regs[ADC_CTRLF] = value;
-                if value & 0x0F != 0 {
-                    println!("[WARNING] SAMPNUM features are not implemented for ADC in this emulator. These bits will be ignored.");
+                if value & 0x0F > 0x0A {
+                    println!("[WARNING] Invalid SAMPNUM specified for ADC. A single sample will be taken.");
                 }
             }
             ADC_COMMAND => {
-                self.regs[ADC_COMMAND] = value & 0x7F;
-                if value & 0x80 != 0 {
-                    println!("[WARNING] DIFF feature is not implemented for ADC in this emulator. This bit will be ignored.");
-                }
-                match value & 0x07 {
-                    0x00 => self.busy = false,
-                    0x01 => {
+                self.regs[ADC_COMMAND] = value;
+                self.mode = match (value >> 4) & 0x07 {
+                    0x00 => ADC_MODE::SINGLE_8BIT,
+                    0x01 => ADC_MODE::SINGLE_12BIT,
+                    0x02 => ADC_MODE::SERIES,
+                    0x03 => ADC_MODE::SERIES_SCALING,
+                    0x04 => ADC_MODE::BURST,
+                    0x05 => ADC_MODE::BURST_SCALING,
+                    _ => {
+                        println!(
+                            "[WARNING] Invalid MODE specified for ADC. ADC will not be functional."
+                        );
+                        ADC_MODE::RESERVED
+                    }
+                };
+                self.start = match value & 0x07 {
+                    0x00 => ADC_START::STOP,
+                    0x01 => ADC_START::IMMEDIATE,
+                    0x02 => ADC_START::MUXPOS,
+                    0x03 => ADC_START::MUXNEG,
+                    0x04 => ADC_START::EVENT,
+                    _ => {
+                        println!("[WARNING] Invalid START specified for ADC. Write to START field will be ignored.");
+                        ADC_START::RESERVED
+                    }
+                };
+                match self.start {
+                    ADC_START::STOP => {
+                        // Abort any conversion and accumulation in progress
+                        self.busy = false;
+                        self.accum = 0;
+                        self.accum_count = 0;
+                    }
+                    ADC_START::IMMEDIATE => {
                         // Reset to STOP if not enabled
                         if self.regs[ADC_CTRLA] & 0x01 == 0 {
+                            self.start = ADC_START::STOP;
                             self.regs[ADC_COMMAND] &= 0xF8;
                         } else {
-                            self.sample();
+                            self.trigger();
                         }
                     }
-                    _ => {
+                    ADC_START::RESERVED => {
+                        self.start = ADC_START::STOP;
                         self.regs[ADC_COMMAND] &= 0xF8;
-                        println!("[WARNING] Only IMMEDIATE start trigger is implemented for ADC in this emulator. Write to START field will be ignored.");
-                    }
-                }
-                match (value >> 4) & 0x07 {
-                    0x00 => self.mode = ADC_MODE::SINGLE_8BIT,
-                    0x01 => self.mode = ADC_MODE::SINGLE_12BIT,
-                    _ => {
-                        self.mode = ADC_MODE::RESERVED;
-                        println!("[WARNING] Accumulation modes are not implemented for ADC in this emulator. ADC will not be functional.");
                     }
+                    _ => {} // Armed, waiting for trigger
                 }
             }
             ADC_PGACTRL => {
-                println!("[WARNING] PGACTRL features are not implemented for ADC in this emulator. This register will be ignored.");
                 self.regs[ADC_PGACTRL] = value;
+                if value >> 5 > 0x04 {
+                    println!(
+                        "[WARNING] Invalid GAIN specified for ADC PGA. A gain of 1X will be used."
+                    );
+                }
             }
             ADC_MUXPOS => {
                 self.regs[ADC_MUXPOS] = value;
@@ -281,20 +662,35 @@ impl MemoryMapped for Adc {
                 self.regs[ADC_MUXNEG] &= 0x3F;
                 self.regs[ADC_MUXNEG] |= value & 0xC0;
                 match value & 0x3F {
-                    0..=16 => self.muxpos = (value & 0x3F) as usize,
-                    0x30 => self.muxpos = 0,
-                    _ => {
-                        println!("[WARNING] Only AIN1..15 and GND MUXPOS selections are implemented for ADC. MUXPOS will revert to GND.");
-                        self.muxpos = 0;
-                    }
+                    0x00..=0x0F | ADC_MUX_GND..=ADC_MUX_DACREF0 => {}
+                    _ => println!(
+                        "[WARNING] Invalid MUXPOS specified for ADC. GND will be selected."
+                    ),
+                }
+                if (value & 0xC0 == 0x40) & (self.regs[ADC_PGACTRL] & 0x01 == 0) {
+                    println!("[WARNING] ADC input selected via PGA but PGA is not enabled.");
+                }
+                if self.start.eq(&ADC_START::MUXPOS) {
+                    self.trigger();
                 }
             }
             ADC_MUXNEG => {
-                println!("[WARNING] MUXNEG features are not implemented for ADC in this emulator. This register will be ignored.");
                 self.regs[ADC_MUXNEG] = value;
                 // VIA field is common
                 self.regs[ADC_MUXPOS] &= 0x3F;
                 self.regs[ADC_MUXPOS] |= value & 0xC0;
+                match value & 0x3F {
+                    0x00..=0x0F | ADC_MUX_GND | ADC_MUX_VDDDIV10 | ADC_MUX_DACREF0 => {}
+                    _ => println!(
+                        "[WARNING] Invalid MUXNEG specified for ADC. GND will be selected."
+                    ),
+                }
+                if (value & 0xC0 == 0x40) & (self.regs[ADC_PGACTRL] & 0x01 == 0) {
+                    println!("[WARNING] ADC input selected via PGA but PGA is not enabled.");
+                }
+                if self.start.eq(&ADC_START::MUXNEG) {
+                    self.trigger();
+                }
             }
             ADC_RESULT0 => self.regs[ADC_TEMP0] = value,
             ADC_RESULT1 => self.regs[ADC_TEMP1] = value,
@@ -331,12 +727,34 @@ impl MemoryMapped for Adc {
 
 impl InterruptSource for Adc {
     fn interrupt(&mut self, mask: u8) -> bool {
+        // WCMP shares the vector of the window comparator source selected by WINSRC
+        let wcmp_vector = if (self.regs[ADC_CTRLD] & 0x08) != 0 {
+            ADC_SAMPRDY
+        } else {
+            ADC_RESRDY
+        };
+        let mask = if (mask & wcmp_vector) != 0 {
+            mask
+        } else {
+            mask & !ADC_WCMP
+        };
         (self.regs[ADC_INTCTRL] & self.regs[ADC_INTFLAGS] & mask) != 0x00
     }
 }
 
 impl Clocked for Adc {
+    fn sleep(&mut self, mode: Option<SleepMode>) -> bool {
+        match mode {
+            Some(SleepMode::Standby) => (self.regs[ADC_CTRLA] & 0x80) != 0, // RUNSTDBY
+            Some(SleepMode::PowerDown) => false,
+            _ => true,
+        }
+    }
+
     fn tick(&mut self, _time: u64) {
+        // Events are only asserted for a single cycle
+        self.ev_out = 0;
+
         // If not enabled we do nothing
         if self.enabled {
             if self.clk_divider > 0 {
@@ -349,38 +767,7 @@ impl Clocked for Adc {
                 // Conversion in process
                 if self.delay == 0 {
                     // Conversion complete
-                    self.busy = false;
-
-                    match self.mode {
-                        ADC_MODE::SINGLE_8BIT => {
-                            self.regs[ADC_RESULT0] = (self.sample >> 4) as u8;
-                            self.regs[ADC_RESULT1] = 0;
-                            self.regs[ADC_RESULT2] = 0;
-                            self.regs[ADC_RESULT3] = 0;
-                        }
-                        ADC_MODE::SINGLE_12BIT => {
-                            if (self.regs[ADC_CTRLF] & 0x10) != 0 {
-                                // Left adjust
-                                self.regs[ADC_RESULT0] = ((self.sample << 4) & 0xFF) as u8;
-                                self.regs[ADC_RESULT1] = (self.sample >> 4) as u8;
-                                self.regs[ADC_RESULT2] = 0;
-                                self.regs[ADC_RESULT3] = 0;
-                            } else {
-                                self.regs[ADC_RESULT0] = (self.sample & 0xFF) as u8;
-                                self.regs[ADC_RESULT1] = (self.sample >> 8) as u8;
-                                self.regs[ADC_RESULT2] = 0;
-                                self.regs[ADC_RESULT3] = 0;
-                            }
-                        }
-                        _ => {} // No other modes implemented
-                    }
-                    self.regs[ADC_INTFLAGS] |= 0x01;
-                    if (self.regs[ADC_CTRLF] & 0x20) != 0 {
-                        // Free running
-                        self.sample();
-                    } else {
-                        self.regs[ADC_COMMAND] &= 0xF8; // STOP
-                    }
+                    self.complete();
                 } else {
                     self.delay -= 1;
                 }
@@ -388,3 +775,19 @@ impl Clocked for Adc {
         }
     }
 }
+
+impl EventUser for Adc {
+    fn event(&mut self, _id: u8, state: bool) {
+        // START conversion on rising edge
+        if state & !self.ev_start_state & self.start.eq(&ADC_START::EVENT) {
+            self.trigger();
+        }
+        self.ev_start_state = state;
+    }
+}
+
+impl EventGenerator for Adc {
+    fn event_state(&self, id: u8) -> bool {
+        (self.ev_out & (1 << id)) != 0
+    }
+}
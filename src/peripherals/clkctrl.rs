@@ -87,36 +87,30 @@ impl MemoryMapped for Clkctrl {
 
     fn write(&mut self, address: usize, value: u8) -> usize {
         match address {
-            CLKCTRL_MCLKCTRLA => {
-                if self.ccp & !self.is_locked() {
-                    self.regs[CLKCTRL_MCLKCTRLA] = value & 0x3;
-                    match value & 0x3 {
-                        0 => self.update_clock(),
-                        1 => self.update_clock(),
-                        2 => println!(
-                            "[WARNING] XOSC32K is not supported. This write will be ignored."
-                        ),
-                        3 => {
-                            self.update_clock();
-                            println!("[WARNING] EXTCLK is set to 8 MHz in this emulator which may not be consistent with hardware.")
-                        }
-                        _ => {}
+            CLKCTRL_MCLKCTRLA if self.ccp & !self.is_locked() => {
+                self.regs[CLKCTRL_MCLKCTRLA] = value & 0x3;
+                match value & 0x3 {
+                    0 => self.update_clock(),
+                    1 => self.update_clock(),
+                    2 => {
+                        println!("[WARNING] XOSC32K is not supported. This write will be ignored.")
                     }
-                    if value & 0x80 != 0 {
-                        println!("[WARNING] CLKOUT feature is not implemented in this emulator. This bit will be ignored.");
+                    3 => {
+                        self.update_clock();
+                        println!("[WARNING] EXTCLK is set to 8 MHz in this emulator which may not be consistent with hardware.")
                     }
+                    _ => {}
                 }
-            }
-            CLKCTRL_MCLKCTRLB => {
-                if self.ccp & !self.is_locked() {
-                    self.regs[CLKCTRL_MCLKCTRLB] = value & 0x1F;
-                    self.update_clock();
+                if value & 0x80 != 0 {
+                    println!("[WARNING] CLKOUT feature is not implemented in this emulator. This bit will be ignored.");
                 }
             }
-            CLKCTRL_MCLKLOCK => {
-                if self.ccp & !self.is_locked() {
-                    self.regs[CLKCTRL_MCLKLOCK] = value & 0x1;
-                }
+            CLKCTRL_MCLKCTRLB if self.ccp & !self.is_locked() => {
+                self.regs[CLKCTRL_MCLKCTRLB] = value & 0x1F;
+                self.update_clock();
+            }
+            CLKCTRL_MCLKLOCK if self.ccp & !self.is_locked() => {
+                self.regs[CLKCTRL_MCLKLOCK] = value & 0x1;
             }
             CLKCTRL_MCLKSTATUS..=CLKCTRL_XOSC32KCTRLA => {
                 println!("[WARNING] CLKCTRL MCLKSTATUS..XOSC32KXTRLA registers are not implemented in this emulator. Writes will be ignored.");
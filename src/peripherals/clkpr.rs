@@ -0,0 +1,79 @@
+use crate::memory::MemoryMapped;
+
+use super::{ClockSource, Clocked};
+
+// Classic AVR system clock prescaler, clocked from the 8 MHz internal oscillator
+const CLKPR_CLKPCE: u8 = 0x80;
+const CLKPR_CLKPS: u8 = 0x0F;
+
+const OSC_PERIOD: u64 = 125;
+
+pub struct Clkpr {
+    reg: u8,
+    clock_period: u64,
+    enable_count: u8,
+}
+
+impl Clkpr {
+    pub fn new() -> Self {
+        // CKDIV8 fuse is programmed by default
+        Clkpr {
+            reg: 0x03,
+            clock_period: OSC_PERIOD << 3,
+            enable_count: 0,
+        }
+    }
+}
+
+impl MemoryMapped for Clkpr {
+    fn get_size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _address: usize) -> (u8, usize) {
+        if self.enable_count > 0 {
+            (self.reg | CLKPR_CLKPCE, 0)
+        } else {
+            (self.reg, 0)
+        }
+    }
+
+    fn write(&mut self, _address: usize, value: u8) -> usize {
+        if value == CLKPR_CLKPCE {
+            // Prescaler may be changed within four cycles
+            self.enable_count = 4;
+        } else if (self.enable_count > 0) & (value & CLKPR_CLKPCE == 0) {
+            self.enable_count = 0;
+            match value & CLKPR_CLKPS {
+                clkps @ 0..=8 => {
+                    self.reg = clkps;
+                    self.clock_period = OSC_PERIOD << clkps;
+                    println!(
+                        "[INFO] CLK_MAIN changed to {:.3} MHz.",
+                        1e3 / (f64::from(self.clock_period as u32))
+                    );
+                }
+                _ => println!(
+                    "[WARNING] Invalid clock prescaler specified. Write to CLKPR will be ignored."
+                ),
+            }
+        } else {
+            println!("[WARNING] CLKPR must be written within four cycles of setting CLKPCE. This write will be ignored.");
+        }
+        0
+    }
+}
+
+impl Clocked for Clkpr {
+    fn tick(&mut self, _time: u64) {
+        if self.enable_count > 0 {
+            self.enable_count -= 1;
+        }
+    }
+}
+
+impl ClockSource for Clkpr {
+    fn clock_period(&self) -> u64 {
+        self.clock_period
+    }
+}
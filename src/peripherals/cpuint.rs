@@ -20,10 +20,12 @@ pub struct Cpuint {
 }
 
 impl Cpuint {
-    pub fn new() -> Self {
+    pub fn new(vectors: usize, flash_size: usize) -> Self {
+        // Vectors are a single word (RJMP) on devices with 8 KB flash or less
+        let shift = if flash_size > 8 * 1024 { 1 } else { 0 };
         let mut table = Vec::new();
-        for i in 0u16..30 {
-            table.push(i << 1);
+        for i in 0..vectors as u16 {
+            table.push(i << shift);
         }
         Cpuint {
             regs: [0; 4],
@@ -65,6 +67,10 @@ impl InterruptHandler for Cpuint {
         if self.regs[CPUINT_STATUS] & 0x01 == 0 {
             for i in 0..self.sources.len() {
                 if self.sources[i].1.borrow_mut().interrupt(self.sources[i].2) {
+                    self.sources[i]
+                        .1
+                        .borrow_mut()
+                        .acknowledge(self.sources[i].2);
                     // Set LVL0EX flag
                     self.regs[CPUINT_STATUS] |= 0x01;
                     // TODO: Handle NMI and priorities correctly
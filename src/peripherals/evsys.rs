@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::memory::MemoryMapped;
+use crate::peripherals::port::Port;
+use crate::peripherals::slpctrl::SleepMode;
+use crate::peripherals::{Clocked, EventGenerator, EventUser, PinMux};
+
+const EVSYS_SWEVENTA: usize = 0x00;
+const EVSYS_CHANNEL0: usize = 0x10;
+const EVSYS_CHANNEL5: usize = 0x15;
+const EVSYS_USER0: usize = 0x20;
+const EVSYS_USERLAST: usize = 0x33;
+
+const EVSYS_CHANNELS: usize = 6;
+
+#[allow(dead_code)]
+#[allow(clippy::type_complexity)]
+pub struct Evsys {
+    name: String,
+    regs: [u8; 0x34],
+    generators: Vec<(u8, u8, Rc<RefCell<dyn EventGenerator>>, u8)>,
+    users: Vec<(usize, Rc<RefCell<dyn EventUser>>, u8)>,
+    channels: [bool; EVSYS_CHANNELS],
+}
+
+impl Evsys {
+    pub fn new(name: String) -> Self {
+        Evsys {
+            name,
+            regs: [0; 0x34],
+            generators: Vec::new(),
+            users: Vec::new(),
+            channels: [false; EVSYS_CHANNELS],
+        }
+    }
+
+    // Generators are registered against the CHANNELn value that selects them.
+    // The channel mask allows for generators (e.g. port pins) that are only
+    // available on, or encoded differently for, a subset of the channels.
+    pub fn add_generator(
+        &mut self,
+        channel_mask: u8,
+        generator: u8,
+        peripheral: Rc<RefCell<dyn EventGenerator>>,
+        id: u8,
+    ) {
+        self.generators
+            .push((channel_mask, generator, peripheral, id));
+    }
+
+    // Users are registered against the offset of their USER register
+    pub fn add_user(&mut self, user: usize, peripheral: Rc<RefCell<dyn EventUser>>, id: u8) {
+        self.users.push((user, peripheral, id));
+    }
+
+    fn generator_state(&self, channel: usize) -> bool {
+        let generator = self.regs[EVSYS_CHANNEL0 + channel];
+        if generator == 0x00 {
+            return false;
+        }
+        for (mask, gen, peripheral, id) in &self.generators {
+            if (*gen == generator) & ((mask & (1 << channel)) != 0) {
+                return peripheral.borrow().event_state(*id);
+            }
+        }
+        false
+    }
+}
+
+impl MemoryMapped for Evsys {
+    fn get_size(&self) -> usize {
+        self.regs.len()
+    }
+
+    fn read(&mut self, address: usize) -> (u8, usize) {
+        match address {
+            EVSYS_SWEVENTA => (0, 0), // Strobe register
+            EVSYS_CHANNEL0..=EVSYS_CHANNEL5 | EVSYS_USER0..=EVSYS_USERLAST => {
+                (self.regs[address], 0)
+            }
+            _ => (0, 0),
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> usize {
+        match address {
+            EVSYS_SWEVENTA => self.regs[EVSYS_SWEVENTA] |= value & 0x3F,
+            EVSYS_CHANNEL0..=EVSYS_CHANNEL5 => {
+                self.regs[address] = value;
+                let channel = address - EVSYS_CHANNEL0;
+                if (value != 0x00)
+                    & !self
+                        .generators
+                        .iter()
+                        .any(|(mask, gen, _, _)| (*gen == value) & ((mask & (1 << channel)) != 0))
+                {
+                    println!("[WARNING] EVSYS generator 0x{:02X} is not implemented in this emulator. Channel {} will remain idle.", value, channel);
+                }
+            }
+            EVSYS_USER0..=EVSYS_USERLAST => {
+                self.regs[address] = value;
+                let mut implemented = false;
+                for (user, peripheral, id) in &self.users {
+                    if *user == address {
+                        implemented = true;
+                        peripheral.borrow_mut().connect(*id, value != 0x00);
+                    }
+                }
+                if (value != 0x00) & !implemented {
+                    println!("[WARNING] EVSYS user at offset 0x{:02X} is not implemented in this emulator. Events will not be delivered.", address);
+                }
+            }
+            _ => {}
+        }
+        0
+    }
+}
+
+impl Clocked for Evsys {
+    // Channels are asynchronous and route events in every sleep mode
+    fn sleep(&mut self, _mode: Option<SleepMode>) -> bool {
+        true
+    }
+
+    fn tick(&mut self, _time: u64) {
+        // Channels carry a level, pulse generators hold their output for one
+        // clock cycle, so users are responsible for their own edge detection.
+        for channel in 0..EVSYS_CHANNELS {
+            self.channels[channel] =
+                self.generator_state(channel) | ((self.regs[EVSYS_SWEVENTA] & (1 << channel)) != 0);
+        }
+        self.regs[EVSYS_SWEVENTA] = 0;
+
+        for (user, peripheral, id) in &self.users {
+            let state = match usize::from(self.regs[*user]) {
+                1..=EVSYS_CHANNELS => self.channels[usize::from(self.regs[*user]) - 1],
+                _ => false,
+            };
+            peripheral.borrow_mut().event(*id, state);
+        }
+    }
+}
+
+// Event output: drives the channel level onto the EVOUTx pin while the user
+// is connected, the pin direction is left to firmware as on hardware
+pub struct Evout {
+    port: Rc<RefCell<Port>>,
+    pin: u8,
+    port_alt: Rc<RefCell<Port>>,
+    pin_alt: u8,
+    mux_alt: bool,
+    connected: bool,
+    state: bool,
+}
+
+impl Evout {
+    pub fn new(port: Rc<RefCell<Port>>, pin: u8, port_alt: Rc<RefCell<Port>>, pin_alt: u8) -> Self {
+        Evout {
+            port,
+            pin,
+            port_alt,
+            pin_alt,
+            mux_alt: false,
+            connected: false,
+            state: false,
+        }
+    }
+
+    fn out(&mut self, state: Option<bool>) {
+        let mut port = if self.mux_alt {
+            self.port_alt.borrow_mut()
+        } else {
+            self.port.borrow_mut()
+        };
+        let pin = if self.mux_alt { self.pin_alt } else { self.pin };
+        match state {
+            Some(state) => port.po_out(pin, state),
+            None => port.po_out_clear(pin),
+        }
+    }
+}
+
+impl EventUser for Evout {
+    fn event(&mut self, _id: u8, state: bool) {
+        if self.connected & (state != self.state) {
+            self.out(Some(state));
+        }
+        self.state = state;
+    }
+
+    fn connect(&mut self, _id: u8, connected: bool) {
+        if connected != self.connected {
+            self.connected = connected;
+            self.out(connected.then_some(self.state));
+        }
+    }
+}
+
+impl PinMux for Evout {
+    fn route(&mut self, route: u8) {
+        self.out(None);
+        self.mux_alt = route != 0;
+        if self.connected {
+            self.out(Some(self.state));
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::memory::MemoryMapped;
+
+use super::port::Port;
+use super::slpctrl::SleepMode;
+use super::{Clocked, InterruptSource};
+
+// Classic AVR external interrupts, INT0/INT1 and the pin change interrupts.
+// PCICR, EICRA and PCMSKn are in extended I/O, PCIFR, EIFR and EIMSK are in
+// I/O space and mapped separately through ExintFlags.
+const EXINT_PCICR: usize = 0x00;
+const EXINT_EICRA: usize = 0x01;
+const EXINT_PCMSK0: usize = 0x03;
+
+const EXINT_PCIFR: usize = 0x00;
+const EXINT_EIFR: usize = 0x01;
+const EXINT_EIMSK: usize = 0x02;
+
+// Interrupt flag masks are INTF0/INTF1 in bits 0 and 1, PCIF0 to PCIF2 in bits 2 to 4
+const EXINT_PCIF_SHIFT: u8 = 2;
+
+pub struct Exint {
+    regs: [u8; 6],
+    flags: [u8; 3],
+    pcint: Vec<Rc<RefCell<Port>>>,
+    int: [(Rc<RefCell<Port>>, u8); 2],
+    pcint_levels: Vec<u8>,
+    int_levels: [bool; 2],
+    clk_io: bool,
+}
+
+impl Exint {
+    pub fn new(pcint: Vec<Rc<RefCell<Port>>>, int: [(Rc<RefCell<Port>>, u8); 2]) -> Self {
+        let pcint_levels = vec![0x00; pcint.len()];
+        Exint {
+            regs: [0; 6],
+            flags: [0; 3],
+            pcint,
+            int,
+            pcint_levels,
+            int_levels: [false; 2],
+            clk_io: true,
+        }
+    }
+
+    fn port_levels(port: &Rc<RefCell<Port>>) -> u8 {
+        let port = port.borrow();
+        (0..8).fold(0, |levels, pin| {
+            levels | (u8::from(port.get_pinstate(pin)) << pin)
+        })
+    }
+
+    fn pending(&self) -> u8 {
+        // INTn in low level mode requests an interrupt while the pin is held low
+        let mut int = self.flags[EXINT_EIFR];
+        for (n, level) in self.int_levels.iter().enumerate() {
+            if ((self.regs[EXINT_EICRA] >> (2 * n)) & 0x03 == 0x00) & !level {
+                int |= 1 << n;
+            }
+        }
+        (int & self.flags[EXINT_EIMSK] & 0x03)
+            | ((self.flags[EXINT_PCIFR] & self.regs[EXINT_PCICR] & 0x07) << EXINT_PCIF_SHIFT)
+    }
+}
+
+impl MemoryMapped for Exint {
+    fn get_size(&self) -> usize {
+        self.regs.len()
+    }
+
+    fn read(&mut self, address: usize) -> (u8, usize) {
+        (self.regs[address], 0)
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> usize {
+        self.regs[address] = match address {
+            EXINT_PCICR => value & 0x07,
+            EXINT_EICRA => value & 0x0F,
+            EXINT_PCMSK0.. => value,
+            _ => 0x00,
+        };
+        0
+    }
+}
+
+pub struct ExintFlags {
+    pub exint: Rc<RefCell<Exint>>,
+}
+
+impl MemoryMapped for ExintFlags {
+    fn get_size(&self) -> usize {
+        3
+    }
+
+    fn read(&mut self, address: usize) -> (u8, usize) {
+        (self.exint.borrow().flags[address], 0)
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> usize {
+        let mut exint = self.exint.borrow_mut();
+        match address {
+            EXINT_PCIFR => exint.flags[EXINT_PCIFR] &= !value, // Flags cleared by writing 1
+            EXINT_EIFR => exint.flags[EXINT_EIFR] &= !value,
+            _ => exint.flags[EXINT_EIMSK] = value & 0x03,
+        }
+        0
+    }
+
+    fn set_bit(&mut self, address: usize, bit: u8, state: bool) -> usize {
+        // SBI clears a single flag, CBI has no effect on flags
+        match address {
+            EXINT_PCIFR | EXINT_EIFR if state => self.write(address, 1 << bit),
+            EXINT_PCIFR | EXINT_EIFR => 0,
+            _ => {
+                let (val, _) = self.read(address);
+                if state {
+                    self.write(address, val | (1 << bit))
+                } else {
+                    self.write(address, val & !(1 << bit))
+                }
+            }
+        }
+    }
+}
+
+impl Clocked for Exint {
+    fn sleep(&mut self, mode: Option<SleepMode>) -> bool {
+        // Pin change and INTn low level are sensed asynchronously, so wake the
+        // device from any sleep mode. Edges on INTn need the I/O clock.
+        self.clk_io = mode != Some(SleepMode::PowerDown);
+        true
+    }
+
+    fn tick(&mut self, _time: u64) {
+        // Pin change flags are set by a change on any pin enabled in PCMSKn
+        for i in 0..self.pcint.len() {
+            let levels = Self::port_levels(&self.pcint[i]);
+            if (levels ^ self.pcint_levels[i]) & self.regs[EXINT_PCMSK0 + i] != 0 {
+                self.flags[EXINT_PCIFR] |= 1 << i;
+            }
+            self.pcint_levels[i] = levels;
+        }
+
+        for n in 0..2 {
+            let level = {
+                let (port, pin) = &self.int[n];
+                port.borrow().get_pinstate(*pin)
+            };
+            let previous = self.int_levels[n];
+            let edge = match (self.regs[EXINT_EICRA] >> (2 * n)) & 0x03 {
+                0x01 => level != previous,
+                0x02 => previous & !level,
+                0x03 => !previous & level,
+                _ => false,
+            };
+            if edge & self.clk_io {
+                self.flags[EXINT_EIFR] |= 1 << n;
+            }
+            self.int_levels[n] = level;
+        }
+    }
+}
+
+impl InterruptSource for Exint {
+    fn interrupt(&mut self, mask: u8) -> bool {
+        (self.pending() & mask) != 0x00
+    }
+
+    fn acknowledge(&mut self, mask: u8) {
+        // Flags are cleared in hardware when the interrupt is serviced
+        self.flags[EXINT_EIFR] &= !(mask & 0x03);
+        self.flags[EXINT_PCIFR] &= !(mask >> EXINT_PCIF_SHIFT);
+    }
+}
@@ -5,11 +5,11 @@ use bitvec::view::BitView;
 
 use crate::hardware::Hardware;
 use crate::memory::MemoryMapped;
-use crate::nets::{Net, NetState, PinState};
+use crate::nets::{Net, NetState, PinState, VIH, VIL};
 
 use bitvec::prelude::*;
 
-use super::InterruptSource;
+use super::{EventGenerator, InterruptSource};
 
 const PORT_DIR: usize = 0x00;
 const PORT_DIRSET: usize = 0x01;
@@ -26,6 +26,9 @@ const PORT_PORTCTRL: usize = 0x0A;
 const PORT_PIN0CTRL: usize = 0x10;
 const PORT_PIN7CTRL: usize = 0x17;
 
+// Px2 and Px6 are fully asynchronous and sense the pin without the synchroniser
+const PORT_ASYNC: u8 = 0x44;
+
 #[allow(clippy::upper_case_acronyms)]
 enum ISC {
     INTDISABLE,
@@ -63,9 +66,9 @@ struct PortIO {
     po_out: bool,
     po_out_val: bool,
     po_dir: bool,
-    po_dir_val: bool, // interrupt sink
-                      // analog sink
-                      // pin state
+    po_dir_val: bool,
+    level: bool, // last defined net level
+    floating: bool,
 }
 
 impl PortIO {
@@ -83,19 +86,25 @@ impl PortIO {
             po_out_val: false,
             po_dir: false,
             po_dir_val: false,
+            level: false,
+            floating: false,
         }
     }
 
     fn update_pinstate(&mut self) {
-        if self.dir | (self.po_dir & self.po_dir_val) {
-            // driven
-            if self.po_out {
-                if self.po_out_val {
-                    *self.pin.borrow_mut() = PinState::DriveH;
-                } else {
-                    *self.pin.borrow_mut() = PinState::DriveL;
-                }
-            } else if self.out {
+        let dir = if self.po_dir {
+            self.po_dir_val
+        } else {
+            self.dir
+        };
+        if dir {
+            // driven, INVEN applies to peripheral overrides too
+            let out = if self.po_out {
+                self.po_out_val
+            } else {
+                self.out
+            };
+            if out ^ self.invert_en {
                 *self.pin.borrow_mut() = PinState::DriveH;
             } else {
                 *self.pin.borrow_mut() = PinState::DriveL;
@@ -110,9 +119,29 @@ impl PortIO {
         }
     }
 
-    fn connect(&mut self, net: Rc<RefCell<Net>>) {
+    fn connect(&mut self, net: Rc<RefCell<Net>>, owner: String) {
         self.net = net;
-        self.net.borrow_mut().connect(Rc::downgrade(&self.pin));
+        self.net
+            .borrow_mut()
+            .connect(Rc::downgrade(&self.pin), owner);
+    }
+
+    fn input(&mut self) -> bool {
+        // Level seen by the input buffer; an undefined net holds the last level
+        let state = self.net.borrow().state;
+        self.floating = false;
+        match state {
+            NetState::High => self.level = true,
+            NetState::Low => self.level = false,
+            NetState::Analog(v) if v >= VIH => self.level = true,
+            NetState::Analog(v) if v <= VIL => self.level = false,
+            _ => self.floating = true,
+        }
+        !self.input_dis & (self.level ^ self.invert_en)
+    }
+
+    fn sensing(&self) -> bool {
+        !matches!(self.isc, ISC::INTDISABLE | ISC::INPUTDISABLE)
     }
 }
 
@@ -121,6 +150,8 @@ pub struct Port {
     name: String,
     pio: [PortIO; 8],
     regs: [u8; 0x18],
+    sync: u8, // first synchroniser stage, IN is the second
+    clk_per: bool,
 }
 
 impl Port {
@@ -138,11 +169,19 @@ impl Port {
                 PortIO::new(),
             ],
             regs: [0u8; 0x18],
+            sync: 0,
+            clk_per: true,
         }
     }
 
+    // CLK_PER is stopped in STANDBY and POWERDOWN sleep modes
+    pub fn clk_per(&mut self, running: bool) {
+        self.clk_per = running;
+    }
+
     pub fn connect(&mut self, pin_index: u8, net: Rc<RefCell<Net>>) {
-        self.pio[usize::from(pin_index)].connect(net);
+        let owner = format!("{}.PIN{}", self.name, pin_index);
+        self.pio[usize::from(pin_index)].connect(net, owner);
     }
 
     fn update_dir(&mut self) {
@@ -163,9 +202,7 @@ impl Port {
         self.pio[n].invert_en = self.regs[PORT_PIN0CTRL + n] & (0x80) != 0;
         self.pio[n].pullup_en = self.regs[PORT_PIN0CTRL + n] & (0x08) != 0;
         self.pio[n].isc = ISC::from(self.regs[PORT_PIN0CTRL + n] & 0x07);
-        if let ISC::INPUTDISABLE = self.pio[n].isc {
-            self.pio[n].input_dis = true;
-        }
+        self.pio[n].input_dis = matches!(self.pio[n].isc, ISC::INPUTDISABLE);
         self.pio[n].update_pinstate();
     }
 
@@ -191,12 +228,20 @@ impl Port {
         self.pio[usize::from(pin_index)].update_pinstate();
     }
 
+    pub fn get_dir(&self, pin_index: u8) -> bool {
+        self.pio[usize::from(pin_index)].dir
+    }
+
     pub fn get_pinstate(&self, pin_index: u8) -> bool {
         self.regs[PORT_IN].view_bits::<Lsb0>()[usize::from(pin_index)]
     }
 
-    pub fn get_netstate(&self, pin_index: u8) -> NetState {
-        self.pio[pin_index as usize].net.borrow().state
+    pub fn get_netvoltage(&self, pin_index: u8) -> f32 {
+        self.pio[pin_index as usize].net.borrow().voltage
+    }
+
+    pub fn get_netresistance(&self, pin_index: u8) -> f32 {
+        self.pio[pin_index as usize].net.borrow().resistance
     }
 }
 
@@ -209,7 +254,7 @@ impl MemoryMapped for Port {
         match address {
             PORT_DIR..=PORT_DIRTGL => (self.regs[PORT_DIR], 0),
             PORT_OUT..=PORT_OUTTGL => (self.regs[PORT_OUT], 0),
-            PORT_IN => (self.regs[PORT_IN], 0), // TODO: update reg value on pin status change
+            PORT_IN => (self.regs[PORT_IN], 0),
             PORT_INTFLAGS => (self.regs[PORT_INTFLAGS], 0),
             PORT_PORTCTRL => (self.regs[PORT_PORTCTRL] & 0x01, 0),
             PORT_PIN0CTRL..=PORT_PIN7CTRL => (self.regs[address] & 0x8F, 0),
@@ -265,29 +310,42 @@ impl MemoryMapped for Port {
 
 impl Hardware for Port {
     fn update(&mut self, _time: u64) {
+        // Pin levels reach IN through a two-cycle synchroniser
+        let input = self.sync;
         for i in 0..8 {
-            match self.pio[i].net.borrow().state {
-                NetState::High => {
-                    if (self.regs[PORT_IN] & (1u8 << i)) == 0x00 {
-                        match self.pio[i].isc {
-                            ISC::RISING | ISC::BOTHEDGES => self.regs[PORT_INTFLAGS] |= 1u8 << i,
-                            _ => {}
-                        }
-                    }
-                    self.regs[PORT_IN] |= 1u8 << i
-                }
-                NetState::Low => {
-                    if (self.regs[PORT_IN] & (1u8 << i)) != 0x00 {
-                        match self.pio[i].isc {
-                            ISC::FALLING | ISC::BOTHEDGES => self.regs[PORT_INTFLAGS] |= 1u8 << i,
-                            _ => {}
-                        }
-                    }
-                    self.regs[PORT_IN] &= !(1u8 << i)
-                }
-                _ => {} // do nothing if undefined
+            let floating = self.pio[i].floating;
+            let level = self.pio[i].input();
+            if self.pio[i].floating & !floating & self.pio[i].sensing() {
+                println!(
+                    "[WARNING] Interrupt sensing on {} pin {} with an undefined net. The last defined level will be used.",
+                    self.name, i
+                );
             }
+            self.sync.view_bits_mut::<Lsb0>().set(i, level);
+        }
+
+        // Without CLK_PER every pin senses asynchronously, but only Px2 and Px6
+        // can detect a single edge
+        let (direct, edges) = if self.clk_per {
+            (PORT_ASYNC, 0xFF)
+        } else {
+            (0xFF, PORT_ASYNC)
+        };
+        let sensed = (self.sync & direct) | (input & !direct);
+        let previous = (input & direct) | (self.regs[PORT_IN] & !direct);
+        let rising = sensed & !previous;
+        let falling = !sensed & previous;
+        for i in 0..8 {
+            let flag = match self.pio[i].isc {
+                ISC::BOTHEDGES => rising | falling,
+                ISC::RISING => rising & edges,
+                ISC::FALLING => falling & edges,
+                ISC::LEVEL => !sensed, // re-triggers for as long as the input is low
+                _ => 0,
+            };
+            self.regs[PORT_INTFLAGS] |= flag & (1u8 << i);
         }
+        self.regs[PORT_IN] = input;
     }
 }
 
@@ -324,6 +382,65 @@ impl MemoryMapped for VirtualPort {
     }
 }
 
+// Classic AVR PINx, DDRx and PORTx registers. PORTx enables the pull-up on
+// input pins; writing one to a PINx bit toggles PORTx.
+pub struct ClassicPort {
+    pub port: Rc<RefCell<Port>>,
+}
+
+impl ClassicPort {
+    fn update_pullups(&mut self) {
+        let mut port = self.port.borrow_mut();
+        let pullups = !port.regs[PORT_DIR] & port.regs[PORT_OUT];
+        for i in 0..8 {
+            let pinctrl = if pullups & (1 << i) != 0 { 0x08 } else { 0x00 };
+            if port.regs[PORT_PIN0CTRL + i] != pinctrl {
+                port.write(PORT_PIN0CTRL + i, pinctrl);
+            }
+        }
+    }
+}
+
+impl MemoryMapped for ClassicPort {
+    fn get_size(&self) -> usize {
+        3
+    }
+
+    fn read(&mut self, address: usize) -> (u8, usize) {
+        self.port.borrow_mut().read(match address {
+            0x00 => PORT_IN,
+            0x01 => PORT_DIR,
+            _ => PORT_OUT,
+        })
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> usize {
+        match address {
+            0x00 => self.port.borrow_mut().write(PORT_OUTTGL, value),
+            0x01 => self.port.borrow_mut().write(PORT_DIR, value),
+            _ => self.port.borrow_mut().write(PORT_OUT, value),
+        };
+        self.update_pullups();
+        0
+    }
+
+    fn set_bit(&mut self, address: usize, bit: u8, state: bool) -> usize {
+        // SBI on PINx toggles a single pin, CBI has no effect
+        match address {
+            0x00 if state => self.write(address, 1 << bit),
+            0x00 => 0,
+            _ => {
+                let (val, _) = self.read(address);
+                if state {
+                    self.write(address, val | (1 << bit))
+                } else {
+                    self.write(address, val & !(1 << bit))
+                }
+            }
+        }
+    }
+}
+
 impl InterruptSource for Port {
     fn interrupt(&mut self, mask: u8) -> bool {
         (((self.regs[PORT_PIN0CTRL] & 0x03) != 0x00) && (self.regs[PORT_INTFLAGS] & 0b00000001 & mask) != 0x00) || // fmt
@@ -336,3 +453,128 @@ impl InterruptSource for Port {
         (((self.regs[PORT_PIN0CTRL + 7] & 0x03) != 0x00) && (self.regs[PORT_INTFLAGS] & 0b10000000 & mask) != 0x00)
     }
 }
+
+impl EventGenerator for Port {
+    fn event_state(&self, id: u8) -> bool {
+        // id is the pin index
+        self.get_pinstate(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYNC_PIN: u8 = 0;
+    const ASYNC_PIN: u8 = 2;
+
+    const ISC_INTDISABLE: u8 = 0;
+    const ISC_BOTHEDGES: u8 = 1;
+    const ISC_RISING: u8 = 2;
+    const ISC_FALLING: u8 = 3;
+    const ISC_LEVEL: u8 = 5;
+
+    // Port with a single pin on a net that is initially low, flags cleared
+    fn setup(pin: u8, isc: u8) -> (Port, Rc<RefCell<Net>>) {
+        let mut port = Port::new("PORTA".to_string());
+        let net = Rc::new(RefCell::new(Net::new("NET".to_string())));
+        net.borrow_mut().state = NetState::Low;
+        port.connect(pin, Rc::clone(&net));
+        port.write(PORT_PIN0CTRL + usize::from(pin), isc);
+        for _ in 0..3 {
+            port.update(0);
+        }
+        port.write(PORT_INTFLAGS, 0xFF);
+        (port, net)
+    }
+
+    // Drives the net and returns INTFLAGS after each of three updates
+    fn drive(port: &mut Port, net: &Rc<RefCell<Net>>, level: bool) -> [u8; 3] {
+        net.borrow_mut().state = if level { NetState::High } else { NetState::Low };
+        let mut flags = [0; 3];
+        for flag in &mut flags {
+            port.update(0);
+            *flag = port.read(PORT_INTFLAGS).0;
+        }
+        flags
+    }
+
+    #[test]
+    fn edge_modes() {
+        // ISC, flag on rising edge, flag on falling edge
+        let cases = [
+            (ISC_INTDISABLE, false, false),
+            (ISC_BOTHEDGES, true, true),
+            (ISC_RISING, true, false),
+            (ISC_FALLING, false, true),
+        ];
+        // Pin, updates before the flag is set
+        for (pin, latency) in [(SYNC_PIN, 2), (ASYNC_PIN, 1)] {
+            for (isc, rise, fall) in cases {
+                let (mut port, net) = setup(pin, isc);
+                for (level, expected) in [(true, rise), (false, fall)] {
+                    let flags = drive(&mut port, &net, level);
+                    for (i, flag) in flags.iter().enumerate() {
+                        let set = (flag & (1 << pin)) != 0;
+                        assert_eq!(
+                            set,
+                            expected & (i + 1 >= latency),
+                            "pin {pin}, ISC {isc}, level {level}, update {i}"
+                        );
+                    }
+                    port.write(PORT_INTFLAGS, 0xFF);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn level_mode() {
+        for pin in [SYNC_PIN, ASYNC_PIN] {
+            // Re-triggers while low, even after the flag is cleared
+            let (mut port, net) = setup(pin, ISC_LEVEL);
+            port.update(0);
+            assert_ne!(port.read(PORT_INTFLAGS).0 & (1 << pin), 0, "pin {pin}");
+
+            drive(&mut port, &net, true);
+            port.write(PORT_INTFLAGS, 0xFF);
+            port.update(0);
+            assert_eq!(port.read(PORT_INTFLAGS).0 & (1 << pin), 0, "pin {pin}");
+
+            let flags = drive(&mut port, &net, false);
+            assert_ne!(flags[2] & (1 << pin), 0, "pin {pin}");
+        }
+    }
+
+    #[test]
+    fn sleep_without_clk_per() {
+        // Synchronous pins only detect BOTHEDGES and LEVEL without CLK_PER,
+        // asynchronous pins detect all modes
+        let cases = [
+            (SYNC_PIN, ISC_INTDISABLE, false),
+            (SYNC_PIN, ISC_BOTHEDGES, true),
+            (SYNC_PIN, ISC_RISING, false),
+            (SYNC_PIN, ISC_FALLING, false),
+            (SYNC_PIN, ISC_LEVEL, true),
+            (ASYNC_PIN, ISC_INTDISABLE, false),
+            (ASYNC_PIN, ISC_BOTHEDGES, true),
+            (ASYNC_PIN, ISC_RISING, true),
+            (ASYNC_PIN, ISC_FALLING, true),
+            (ASYNC_PIN, ISC_LEVEL, true),
+        ];
+        for (pin, isc, wakes) in cases {
+            let (mut port, net) = setup(pin, isc);
+            drive(&mut port, &net, true);
+            port.write(PORT_INTFLAGS, 0xFF);
+            port.clk_per(false);
+            let flags = drive(&mut port, &net, false)[0]
+                | drive(&mut port, &net, true)[0]
+                | drive(&mut port, &net, false)[0];
+            assert_eq!(
+                (flags & (1 << pin)) != 0,
+                wakes,
+                "pin {pin}, ISC {isc} without CLK_PER"
+            );
+        }
+    }
+}
@@ -1,17 +1,33 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use crate::memory::MemoryMapped;
+use crate::peripherals::PinMux;
 
 const PORTMUX_EVSYSROUTEA: usize = 0x00;
 const PORTMUX_TCBROUTEA: usize = 0x05;
 
 #[allow(dead_code)]
+#[allow(clippy::type_complexity)]
 pub struct Portmux {
     name: String,
     regs: [u8; 6],
+    routes: Vec<(usize, u8, Rc<RefCell<dyn PinMux>>)>,
 }
 
 impl Portmux {
     pub fn new(name: String) -> Self {
-        Portmux { name, regs: [0; 6] }
+        Portmux {
+            name,
+            regs: [0; 6],
+            routes: Vec::new(),
+        }
+    }
+
+    // Peripherals are registered against the ROUTEA register and the mask of
+    // the field that selects their pins; the field is passed right aligned
+    pub fn add_route(&mut self, register: usize, mask: u8, peripheral: Rc<RefCell<dyn PinMux>>) {
+        self.routes.push((register, mask, peripheral));
     }
 }
 
@@ -22,18 +38,30 @@ impl MemoryMapped for Portmux {
 
     fn read(&mut self, address: usize) -> (u8, usize) {
         match address {
-            PORTMUX_EVSYSROUTEA..=PORTMUX_TCBROUTEA => {
-                println!("[WARNING] PORTMUX is not implemented in this emulator. Reads of PORTMUX registers will return last written value.");
-                (self.regs[address], 0)
-            }
+            PORTMUX_EVSYSROUTEA..=PORTMUX_TCBROUTEA => (self.regs[address], 0),
             _ => (0, 0),
         }
     }
 
     fn write(&mut self, address: usize, value: u8) -> usize {
         if let PORTMUX_EVSYSROUTEA..=PORTMUX_TCBROUTEA = address {
+            let changed = self.regs[address] ^ value;
             self.regs[address] = value;
-            println!("[WARNING] PORTMUX is not implemented in this emulator. Writes to PORTMUX registers will have no effect.");
+
+            let mut implemented = 0x00;
+            for (register, mask, peripheral) in &self.routes {
+                if *register == address {
+                    implemented |= mask;
+                    if (changed & mask) != 0 {
+                        peripheral
+                            .borrow_mut()
+                            .route((value & mask) >> mask.trailing_zeros());
+                    }
+                }
+            }
+            if (changed & value & !implemented) != 0 {
+                println!("[WARNING] PORTMUX routing for peripherals not implemented in this emulator will have no effect (register 0x{:02X}, value 0x{:02X}).", address, value);
+            }
         }
         0
     }
@@ -0,0 +1,72 @@
+use crate::cores::SleepController;
+use crate::memory::MemoryMapped;
+
+const SLPCTRL_CTRLA: usize = 0x00;
+
+const SLPCTRL_SEN: u8 = 0x01;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SleepMode {
+    Idle,
+    Standby,
+    PowerDown,
+}
+
+pub struct Slpctrl {
+    regs: [u8; 1],
+    mode: Option<SleepMode>,
+}
+
+impl Slpctrl {
+    pub fn new() -> Self {
+        Slpctrl {
+            regs: [0; 1],
+            mode: None,
+        }
+    }
+}
+
+impl MemoryMapped for Slpctrl {
+    fn get_size(&self) -> usize {
+        self.regs.len()
+    }
+
+    fn read(&mut self, address: usize) -> (u8, usize) {
+        (self.regs[address], 0)
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> usize {
+        if address == SLPCTRL_CTRLA {
+            self.regs[SLPCTRL_CTRLA] = value & 0x07;
+        }
+        0
+    }
+}
+
+impl SleepController for Slpctrl {
+    fn sleep(&mut self) -> bool {
+        if (self.regs[SLPCTRL_CTRLA] & SLPCTRL_SEN) == 0 {
+            return false;
+        }
+        self.mode = match (self.regs[SLPCTRL_CTRLA] >> 1) & 0x03 {
+            0 => Some(SleepMode::Idle),
+            1 => Some(SleepMode::Standby),
+            2 => Some(SleepMode::PowerDown),
+            _ => {
+                println!(
+                    "[WARNING] Reserved sleep mode selected in SLPCTRL. SLEEP will be ignored."
+                );
+                None
+            }
+        };
+        self.mode.is_some()
+    }
+
+    fn wake(&mut self) {
+        self.mode = None;
+    }
+
+    fn mode(&self) -> Option<SleepMode> {
+        self.mode
+    }
+}
@@ -0,0 +1,61 @@
+use crate::cores::SleepController;
+use crate::memory::MemoryMapped;
+
+use super::slpctrl::SleepMode;
+
+// Classic AVR sleep mode control register
+const SMCR_SE: u8 = 0x01;
+
+pub struct Smcr {
+    reg: u8,
+    mode: Option<SleepMode>,
+}
+
+impl Smcr {
+    pub fn new() -> Self {
+        Smcr { reg: 0, mode: None }
+    }
+}
+
+impl MemoryMapped for Smcr {
+    fn get_size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _address: usize) -> (u8, usize) {
+        (self.reg, 0)
+    }
+
+    fn write(&mut self, _address: usize, value: u8) -> usize {
+        self.reg = value & 0x0F;
+        0
+    }
+}
+
+impl SleepController for Smcr {
+    fn sleep(&mut self) -> bool {
+        if (self.reg & SMCR_SE) == 0 {
+            return false;
+        }
+        // Timer/Counter2 and the ADC are not emulated, so ADC noise reduction
+        // is equivalent to IDLE, and power-save and the standby modes to
+        // power-down
+        self.mode = match (self.reg >> 1) & 0x07 {
+            0 | 1 => Some(SleepMode::Idle),
+            2 | 3 | 6 | 7 => Some(SleepMode::PowerDown),
+            _ => {
+                println!("[WARNING] Reserved sleep mode selected in SMCR. SLEEP will be ignored.");
+                None
+            }
+        };
+        self.mode.is_some()
+    }
+
+    fn wake(&mut self) {
+        self.mode = None;
+    }
+
+    fn mode(&self) -> Option<SleepMode> {
+        self.mode
+    }
+}
@@ -2,7 +2,7 @@ use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
-use super::{Clocked, InterruptSource};
+use super::{Clocked, InterruptSource, PinMux};
 use crate::memory::MemoryMapped;
 use crate::peripherals::port::Port;
 
@@ -29,16 +29,20 @@ pub struct Spi {
     has_data_tx: bool,
     sr_tx: u8,
     sr_rx: u8,
-    sr_state: u8,
+    host_active: bool,
+    host_edge: u8,
     port: Rc<RefCell<Port>>,
     pins: [u8; 4],
     port_alt: Rc<RefCell<Port>>,
     pins_alt: [u8; 4],
-    pub mux_alt: bool,
+    mux: u8,
     ps_count: u8,
-    subinterval: u8,
     state_sck: bool,
     intflags_set: u8,
+    client_ss: bool,
+    client_bits: u8,
+    client_miso: bool,
+    sr_loaded: bool,
 }
 
 impl Spi {
@@ -58,21 +62,28 @@ impl Spi {
             has_data_tx: false,
             sr_tx: 0,
             sr_rx: 0,
-            sr_state: 0,
+            host_active: false,
+            host_edge: 0,
             port,
             pins,
             port_alt,
             pins_alt,
-            mux_alt: false,
+            mux: 0,
             ps_count: 0,
-            subinterval: 0,
             state_sck: false,
             intflags_set: 0,
+            client_ss: true,
+            client_bits: 0,
+            client_miso: false,
+            sr_loaded: false,
         }
     }
 
-    fn handle_read_intflags(&self) -> u8 {
-        // TODO: Handle flag clear process
+    fn handle_read_intflags(&mut self) -> u8 {
+        if !self.is_bufen() {
+            // Flags are cleared by reading INTFLAGS then accessing DATA
+            self.intflags_set |= self.regs[SPI_INTFLAGS] & 0xC0;
+        }
         self.regs[SPI_INTFLAGS]
     }
 
@@ -83,7 +94,6 @@ impl Spi {
     }
 
     fn handle_read_data(&mut self) -> u8 {
-        // Only master mode implemented
         if self.is_bufen() {
             // Buffered
             if !self.buf_rx.is_empty() {
@@ -108,14 +118,18 @@ impl Spi {
     }
 
     fn handle_write_data(&mut self, value: u8) {
-        // Only master mode implemented
+        if !self.is_master() {
+            self.handle_write_data_client(value);
+            return;
+        }
         if self.is_bufen() {
             // Buffered
-            if self.sr_state == 0 {
+            if !self.host_active {
                 // Idle
                 self.sr_tx = value;
-                self.sr_state = 8;
-            } else {
+                self.host_active = true;
+                self.host_edge = 0;
+            } else if !self.has_data_tx {
                 // Transmitting
                 self.data_tx = value;
                 self.has_data_tx = true;
@@ -130,11 +144,263 @@ impl Spi {
             self.regs[SPI_INTFLAGS] &= !self.intflags_set;
             self.intflags_set = 0x00;
 
-            if self.sr_state > 0 {
+            if self.host_active {
+                // Write is ignored during a transfer
                 self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(6, true); // WRCOL
+            } else {
+                self.sr_tx = value;
+                self.host_active = true;
+                self.host_edge = 0;
+            }
+        }
+    }
+
+    fn handle_write_data_client(&mut self, value: u8) {
+        if self.is_bufen() {
+            if self.is_bufwr() & self.client_ss & !self.sr_loaded {
+                // First write while deselected goes directly to the shift register
+                self.sr_tx = value;
+                self.sr_loaded = true;
+            } else {
+                self.data_tx = value;
+                self.has_data_tx = true;
+                self.regs[SPI_INTFLAGS]
+                    .view_bits_mut::<Lsb0>()
+                    .set(5, false);
+            }
+        } else {
+            // Clear INTFLAGS if interrupt has been serviced
+            self.regs[SPI_INTFLAGS] &= !self.intflags_set;
+            self.intflags_set = 0x00;
+
+            if !self.client_ss & (self.client_bits > 0) {
+                // Write is ignored during a transfer
+                self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(6, true); // WRCOL
+            } else {
+                self.sr_tx = value;
+            }
+        }
+    }
+
+    fn bit_index(&self, n: u8) -> usize {
+        if self.is_lsb_first() {
+            usize::from(n)
+        } else {
+            usize::from(7 - n)
+        }
+    }
+
+    fn client_complete(&mut self) {
+        let data = self.sr_rx;
+        self.client_bits = 0;
+        self.sr_rx = 0;
+        self.sr_loaded = false;
+        // Shift register is common to transmit and receive, so unless new data
+        // is loaded the received byte is shifted out in the next transfer
+        self.sr_tx = data;
+        if self.is_bufen() {
+            self.buf_rx.push_back(data);
+            if self.buf_rx.len() > 2 {
+                // Discard oldest data
+                self.buf_rx.pop_front();
+                self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(0, true); // buffer overflow
+            }
+            self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(7, true); // recieve complete
+            if self.has_data_tx {
+                self.sr_tx = self.data_tx;
+                self.sr_loaded = true;
+                self.has_data_tx = false;
+                self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(5, true); // data reg empty
+            } else {
+                self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(6, true); // transfer complete
+            }
+        } else {
+            self.data_rx = data;
+            self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(7, true); // transfer complete
+        }
+    }
+
+    fn client_tick(&mut self) {
+        let (port, pins) = match self.mux {
+            0 => (Rc::clone(&self.port), self.pins),
+            1 => (Rc::clone(&self.port_alt), self.pins_alt),
+            _ => return, // NONE, not connected to any pins
+        };
+        let mut port = port.borrow_mut();
+
+        // MOSI, SCK and SS are inputs in client mode
+        port.po_dir(pins[SPI_PIN_MOSI], false);
+        port.po_dir(pins[SPI_PIN_SCK], false);
+        port.po_dir(pins[SPI_PIN_SS], false);
+
+        let ss = port.get_pinstate(pins[SPI_PIN_SS]);
+        let sck = port.get_pinstate(pins[SPI_PIN_SCK]);
+        let cpol = (self.mode() & 0x02) != 0;
+        let cpha = (self.mode() & 0x01) != 0;
+
+        if ss {
+            // Deselected, any partial transfer is discarded
+            self.client_ss = true;
+            self.client_bits = 0;
+            self.sr_rx = 0;
+            self.state_sck = sck;
+            // MISO is tri-stated
+            port.po_out_clear(pins[SPI_PIN_MISO]);
+            port.po_dir(pins[SPI_PIN_MISO], false);
+            return;
+        }
+
+        if self.client_ss {
+            // Selected
+            self.client_ss = false;
+            self.state_sck = sck;
+            if self.is_bufen() {
+                self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(4, true); // client select
+            }
+        }
+
+        if sck != self.state_sck {
+            self.state_sck = sck;
+            let leading = sck != cpol;
+            if leading != cpha {
+                // Sample
+                let index = self.bit_index(self.client_bits);
+                self.sr_rx
+                    .view_bits_mut::<Lsb0>()
+                    .set(index, port.get_pinstate(pins[SPI_PIN_MOSI]));
+                self.client_bits += 1;
+                if self.client_bits == 8 {
+                    self.client_complete();
+                }
+            } else {
+                // Setup
+                self.client_miso = self.sr_tx.view_bits::<Lsb0>()[self.bit_index(self.client_bits)];
+            }
+        } else if !cpha & (self.client_bits == 0) & (sck == cpol) {
+            // First bit must be valid before the first leading edge
+            self.client_miso = self.sr_tx.view_bits::<Lsb0>()[self.bit_index(0)];
+        }
+
+        // MISO direction is user defined
+        port.po_dir_clear(pins[SPI_PIN_MISO]);
+        port.po_out(pins[SPI_PIN_MISO], self.client_miso);
+    }
+
+    fn host_complete(&mut self) {
+        let data = self.sr_rx;
+        self.sr_rx = 0;
+        self.host_active = false;
+        if self.is_bufen() {
+            self.buf_rx.push_back(data);
+            if self.buf_rx.len() > 2 {
+                // Discard oldest data
+                self.buf_rx.pop_front();
+                self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(0, true); // buffer overflow
+            }
+            self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(7, true); // recieve complete
+            if self.has_data_tx {
+                // Commence new transfer
+                self.sr_tx = self.data_tx;
+                self.has_data_tx = false;
+                self.host_active = true;
+                self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(5, true); // data reg empty
+            } else {
+                self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(6, true); // transfer complete
+            }
+        } else {
+            self.data_rx = data;
+            self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(7, true); // transfer complete
+        }
+    }
+
+    fn host_select(&mut self, port: &mut Port, pins: [u8; 4]) -> bool {
+        // Returns false if host mode has been lost to another host
+        port.po_dir_clear(pins[SPI_PIN_SS]);
+        if !self.is_ssd() & !port.get_dir(pins[SPI_PIN_SS]) {
+            // Multi-host, SS is only sensed while it is configured as an input
+            // and driving it low forces client mode. As an output it is an
+            // ordinary GPIO, typically used to select a client.
+            if !port.get_pinstate(pins[SPI_PIN_SS]) {
+                println!("[WARNING] SS pin driven low while SPI is in host mode with SSD clear. SPI will switch to client mode.");
+                self.regs[SPI_CTRLA] &= !0x20;
+                self.host_active = false;
+                self.has_data_tx = false;
+                // SSIF in buffered mode, otherwise IF
+                self.regs[SPI_INTFLAGS] |= if self.is_bufen() { 0x10 } else { 0x80 };
+                for pin in pins {
+                    port.po_out_clear(pin);
+                    port.po_dir_clear(pin);
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    fn host_tick(&mut self, port: &mut Port, pins: [u8; 4]) {
+        let cpol = (self.mode() & 0x02) != 0;
+        let cpha = (self.mode() & 0x01) != 0;
+
+        // MISO is an input, MOSI and SCK directions are user defined
+        port.po_dir(pins[SPI_PIN_MISO], false);
+
+        if !self.host_active {
+            // Idle
+            self.state_sck = cpol;
+            port.po_out(pins[SPI_PIN_SCK], cpol);
+            return;
+        }
+
+        if self.host_edge == 0 {
+            // Half a period before the first edge
+            if !cpha {
+                let mosi = self.sr_tx.view_bits::<Lsb0>()[self.bit_index(0)];
+                port.po_out(pins[SPI_PIN_MOSI], mosi);
+            }
+            self.host_edge = 1;
+            return;
+        }
+
+        let leading = (self.host_edge & 1) == 1;
+        self.state_sck = leading != cpol;
+        port.po_out(pins[SPI_PIN_SCK], self.state_sck);
+        if leading != cpha {
+            // Sample
+            let index = self.bit_index((self.host_edge - 1) >> 1);
+            self.sr_rx
+                .view_bits_mut::<Lsb0>()
+                .set(index, port.get_pinstate(pins[SPI_PIN_MISO]));
+        } else if self.host_edge < 16 {
+            // Setup
+            let mosi = self.sr_tx.view_bits::<Lsb0>()[self.bit_index(self.host_edge >> 1)];
+            port.po_out(pins[SPI_PIN_MOSI], mosi);
+        }
+
+        if self.host_edge == 16 {
+            self.host_complete();
+            self.host_edge = 0;
+            if self.host_active & !cpha {
+                // Back to back transfer, first bit is set up on the last edge
+                let mosi = self.sr_tx.view_bits::<Lsb0>()[self.bit_index(0)];
+                port.po_out(pins[SPI_PIN_MOSI], mosi);
+                self.host_edge = 1;
+            }
+        } else {
+            self.host_edge += 1;
+        }
+    }
+
+    fn release_pins(&self) {
+        let pins = match self.mux {
+            0 => Some((&self.port, self.pins)),
+            1 => Some((&self.port_alt, self.pins_alt)),
+            _ => None,
+        };
+        if let Some((port, pins)) = pins {
+            for pin in pins {
+                port.borrow_mut().po_out_clear(pin);
+                port.borrow_mut().po_dir_clear(pin);
             }
-            self.sr_tx = value;
-            self.sr_state = 8;
         }
     }
 
@@ -174,7 +440,10 @@ impl Spi {
         self.regs[SPI_CTRLB].view_bits::<Lsb0>()[7]
     }
 
-    #[allow(dead_code)]
+    fn is_ssd(&self) -> bool {
+        self.regs[SPI_CTRLB].view_bits::<Lsb0>()[2]
+    }
+
     fn is_bufwr(&self) -> bool {
         self.regs[SPI_CTRLB].view_bits::<Lsb0>()[6]
     }
@@ -190,16 +459,32 @@ impl MemoryMapped for Spi {
             SPI_CTRLA..=SPI_INTCTRL => (self.regs[address], 0),
             SPI_INTFLAGS => (self.handle_read_intflags(), 0),
             SPI_DATA => (self.handle_read_data(), 0),
-            _ => panic!("Attempt to access invalid register in SPI peripheral."),
+            _ => (0, 0),
         }
     }
 
     fn write(&mut self, address: usize, value: u8) -> usize {
         match address {
-            SPI_CTRLA..=SPI_INTCTRL => self.regs[address] = value,
+            SPI_CTRLA => {
+                if (self.regs[SPI_CTRLA] ^ value) & 0x21 != 0 {
+                    // Overrides differ between host and client mode
+                    self.release_pins();
+                    self.client_ss = true;
+                    self.client_bits = 0;
+                }
+                self.regs[SPI_CTRLA] = value;
+            }
+            SPI_CTRLB => {
+                self.regs[SPI_CTRLB] = value;
+                if self.is_bufen() & !self.has_data_tx {
+                    self.regs[SPI_INTFLAGS].view_bits_mut::<Lsb0>().set(5, true);
+                    // data reg empty
+                }
+            }
+            SPI_INTCTRL => self.regs[address] = value,
             SPI_INTFLAGS => self.handle_write_intflags(value),
             SPI_DATA => self.handle_write_data(value),
-            _ => panic!("Attempt to access invalid register in SPI peripheral."),
+            _ => {}
         }
         0
     }
@@ -225,136 +510,109 @@ impl InterruptSource for Spi {
 
 impl Clocked for Spi {
     fn tick(&mut self, _time: u64) {
-        // Prescaler
-        if self.ps_count == 0 {
-            // Reset counter
-            self.ps_count = self.prescaler() >> 1; // We need a double speed clock to synth sck
+        if !self.is_enabled() {
+            self.ps_count = 0;
+            return;
+        }
 
-            let mut port;
-            let pins;
+        if !self.is_master() {
+            // Client mode is clocked by the external SCK, not the prescaler
+            self.client_tick();
+            return;
+        }
 
-            if self.mux_alt {
-                port = self.port_alt.borrow_mut();
-                pins = self.pins_alt;
-            } else {
-                port = self.port.borrow_mut();
-                pins = self.pins;
+        let (port, pins) = match self.mux {
+            0 => (Rc::clone(&self.port), self.pins),
+            1 => (Rc::clone(&self.port_alt), self.pins_alt),
+            _ => {
+                // NONE, not connected to any pins; transfers are abandoned
+                self.host_active = false;
+                return;
             }
+        };
+        let mut port = port.borrow_mut();
 
-            // TICK!
-            if self.is_enabled() {
-                if self.is_master() {
-                    // Master mode
-                    if (self.sr_state == 0) & (self.subinterval == 0) {
-                        // Idle
-                        match self.mode() {
-                            0..=1 => {
-                                self.state_sck = false;
-                                port.po_out(pins[SPI_PIN_SCK], false)
-                            } // Clock idles low
-                            2..=3 => {
-                                self.state_sck = true;
-                                port.po_out(pins[SPI_PIN_SCK], true)
-                            } // Clock idles high
-                            _ => panic!("Invalid SPI mode specified."),
-                        }
-                    } else {
-                        // New transfer
-                        if (self.sr_state == 8) & (self.subinterval == 0) {
-                            self.subinterval = 16;
-                            if !self.is_lsb_first() {
-                                self.sr_tx.view_bits_mut::<Lsb0>().reverse();
-                            }
-                        }
-
-                        match self.mode() {
-                            0 => {
-                                if self.subinterval < 16 {
-                                    self.state_sck = !self.state_sck;
-                                    port.po_out(pins[SPI_PIN_SCK], self.state_sck);
-                                }
-                                if self.subinterval % 2 == 0 {
-                                    let mosi = self.sr_tx.view_bits::<Lsb0>()[0];
-                                    port.po_out(pins[SPI_PIN_MOSI], mosi);
-                                    self.sr_tx >>= 1;
-                                    self.sr_rx >>= 1;
-                                    self.sr_tx
-                                        .view_bits_mut::<Lsb0>()
-                                        .set(7, port.get_pinstate(pins[SPI_PIN_MISO]));
-                                    self.sr_state -= 1;
-                                }
-                                if self.subinterval == 1 {
-                                    // Reorder recieve data if required
-                                    if !self.is_lsb_first() {
-                                        self.sr_rx.view_bits_mut::<Lsb0>().reverse();
-                                    }
-                                    if self.is_bufen() {
-                                        // Buffer recieve data
-                                        self.buf_rx.push_back(self.sr_rx);
-                                        if self.buf_rx.len() > 2 {
-                                            // Discard oldest data
-                                            self.buf_rx.pop_front();
-                                            self.regs[SPI_INTFLAGS]
-                                                .view_bits_mut::<Lsb0>()
-                                                .set(0, true); // buffer overflow
-                                        }
-                                        self.regs[SPI_INTFLAGS]
-                                            .view_bits_mut::<Lsb0>()
-                                            .set(7, true); // recieve complete
-                                                           // Buffered, check for data in buffer
-                                        if self.has_data_tx {
-                                            self.sr_tx = self.data_tx;
-                                            if !self.is_lsb_first() {
-                                                self.sr_tx.view_bits_mut::<Lsb0>().reverse();
-                                            }
-                                            self.has_data_tx = false;
-                                            // Commence new transfer
-                                            self.subinterval = 16;
-                                            self.sr_state = 8;
-                                            self.regs[SPI_INTFLAGS]
-                                                .view_bits_mut::<Lsb0>()
-                                                .set(5, true); // data reg empty
-                                        } else {
-                                            self.subinterval = 0;
-                                            self.regs[SPI_INTFLAGS]
-                                                .view_bits_mut::<Lsb0>()
-                                                .set(6, true); // transfer complete
-                                        }
-                                    } else {
-                                        self.subinterval = 0;
-                                        self.data_rx = self.sr_rx;
-                                        self.regs[SPI_INTFLAGS]
-                                            .view_bits_mut::<Lsb0>()
-                                            .set(7, true); // transfer complete
-                                    }
-                                } else {
-                                    self.subinterval -= 1;
-                                }
-                            }
-                            1 => {}
-                            2 => {}
-                            3 => {}
-                            _ => panic!("Invalid SPI mode."),
-                        }
-                    }
-                } else {
-                    // Client mode
-                    //
-                    // Not implemented, but lets at least set the port overrides
-                    port.po_dir(pins[SPI_PIN_MOSI], false);
-                    port.po_dir(pins[SPI_PIN_SCK], false);
-                    port.po_dir(pins[SPI_PIN_SS], false);
-                    port.po_out(pins[SPI_PIN_MISO], false);
-                }
-            } else {
-                // We are not enabled, so lets make sure any port overrides are relinquished
-                for pin in pins {
-                    port.po_out_clear(pin);
-                    port.po_dir_clear(pin);
-                }
-            }
+        if !self.host_select(&mut port, pins) {
+            return;
+        }
+
+        // Prescaler, ticks at twice the SCK frequency
+        if self.ps_count == 0 {
+            self.ps_count = (self.prescaler() >> 1) - 1;
+            self.host_tick(&mut port, pins);
         } else {
             self.ps_count -= 1;
         }
     }
 }
+
+impl PinMux for Spi {
+    fn route(&mut self, route: u8) {
+        // Overrides on the new pins are applied on the next tick
+        self.release_pins();
+        self.mux = route;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::Hardware;
+    use crate::nets::{Net, NetState};
+
+    const PINS: [u8; 4] = [1, 2, 3, 4];
+
+    // PORT DIR and OUT offsets
+    const PORT_DIR: usize = 0x00;
+    const PORT_OUT: usize = 0x04;
+
+    // Host with SSD clear, SS on a net that is held low
+    fn setup(ss_output: bool) -> (Spi, Rc<RefCell<Port>>) {
+        let port = Rc::new(RefCell::new(Port::new("PORTA".to_string())));
+        let net = Rc::new(RefCell::new(Net::new("SS".to_string())));
+        net.borrow_mut().state = NetState::Low;
+        {
+            let mut port = port.borrow_mut();
+            port.connect(PINS[SPI_PIN_SS], net);
+            if ss_output {
+                port.write(PORT_OUT, 0x00);
+                port.write(PORT_DIR, 1 << PINS[SPI_PIN_SS]);
+            }
+            port.update(0);
+        }
+        let mut spi = Spi::new(
+            "SPI0".to_string(),
+            Rc::clone(&port),
+            PINS,
+            Rc::clone(&port),
+            PINS,
+        );
+        spi.write(SPI_CTRLB, 0x00);
+        spi.write(SPI_CTRLA, 0x21); // MASTER, ENABLE
+        (spi, port)
+    }
+
+    fn run(spi: &mut Spi, port: &Rc<RefCell<Port>>) {
+        for time in 0..16 {
+            spi.tick(time);
+            port.borrow_mut().update(time);
+        }
+    }
+
+    #[test]
+    fn ss_output_keeps_host_mode() {
+        let (mut spi, port) = setup(true);
+        run(&mut spi, &port);
+        assert!(spi.is_master());
+        assert_eq!(spi.read(SPI_INTFLAGS).0, 0x00);
+        assert!(port.borrow().get_dir(PINS[SPI_PIN_SS]));
+    }
+
+    #[test]
+    fn ss_input_low_selects_client_mode() {
+        let (mut spi, port) = setup(false);
+        run(&mut spi, &port);
+        assert!(!spi.is_master());
+        assert_eq!(spi.read(SPI_INTFLAGS).0, 0x80); // IF
+    }
+}
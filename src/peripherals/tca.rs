@@ -2,8 +2,11 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::memory::MemoryMapped;
+use crate::peripherals::slpctrl::SleepMode;
 use crate::peripherals::Clocked;
+use crate::peripherals::EventGenerator;
 use crate::peripherals::InterruptSource;
+use crate::peripherals::PinMux;
 
 use super::port::Port;
 
@@ -73,7 +76,9 @@ pub struct Tca {
     port: Rc<RefCell<Port>>,
     pins: [u8; 3],
     pins_alt: [u8; 3],
-    pub mux_alt: [bool; 3],
+    mux_alt: [bool; 3],
+    clk_out: bool,
+    ev_out: u8,
 }
 
 impl Tca {
@@ -92,6 +97,26 @@ impl Tca {
             pins,
             pins_alt,
             mux_alt: [false; 3],
+            clk_out: false,
+            ev_out: 0,
+        }
+    }
+
+    // True for the system clock cycles on which the prescaled TCA clock
+    // (CLK_TCA) ticks; used by peripherals which can be clocked from TCA
+    pub fn clk_out(&self) -> bool {
+        self.clk_out
+    }
+
+    fn wo_out(&self, i: usize, state: Option<bool>) {
+        let pin = if self.mux_alt[i] {
+            self.pins_alt[i]
+        } else {
+            self.pins[i]
+        };
+        match state {
+            Some(wo) => self.port.borrow_mut().po_out(pin, wo),
+            None => self.port.borrow_mut().po_out_clear(pin),
         }
     }
 }
@@ -172,28 +197,16 @@ impl MemoryMapped for Tca {
                     0x07 => TCA_CLKSEL::DIV1024,
                     _ => TCA_CLKSEL::DIV1,
                 };
-                if value & 0x80 != 0 {
-                    println!("[WARNING] RUNSTDBY feature is not implemented for TCA in this emulator. This bit will be ignored.");
-                }
             }
             TCA_CTRLB => {
                 self.regs[TCA_CTRLB] = value;
                 for i in 0..3 {
                     if ((value >> 4) & (1 << i)) == 0 {
                         // WO disabled
-                        if self.mux_alt[i] {
-                            self.port.borrow_mut().po_out_clear(self.pins_alt[i]);
-                        } else {
-                            self.port.borrow_mut().po_out_clear(self.pins[i]);
-                        }
+                        self.wo_out(i, None);
                     } else {
                         // WO enabled
-                        let wo = (self.regs[TCA_CTRLC] & (1 << i)) != 0;
-                        if self.mux_alt[i] {
-                            self.port.borrow_mut().po_out(self.pins_alt[i], wo);
-                        } else {
-                            self.port.borrow_mut().po_out(self.pins[i], wo);
-                        }
+                        self.wo_out(i, Some((self.regs[TCA_CTRLC] & (1 << i)) != 0));
                     }
                 }
                 self.cntmode = match value & 0x07 {
@@ -328,7 +341,19 @@ impl InterruptSource for Tca {
 }
 
 impl Clocked for Tca {
+    fn sleep(&mut self, mode: Option<SleepMode>) -> bool {
+        match mode {
+            Some(SleepMode::Standby) => (self.regs[TCA_CTRLA] & 0x80) != 0, // RUNSTDBY
+            Some(SleepMode::PowerDown) => false,
+            _ => true,
+        }
+    }
+
     fn tick(&mut self, _time: u64) {
+        // Events and CLK_TCA are only asserted for a single cycle
+        self.clk_out = false;
+        self.ev_out = 0;
+
         // If not enabled we do nothing
         if self.enabled {
             if self.clk_divider > 0 {
@@ -346,6 +371,7 @@ impl Clocked for Tca {
                 TCA_CLKSEL::DIV256 => self.clk_divider = 255,
                 TCA_CLKSEL::DIV1024 => self.clk_divider = 1023,
             }
+            self.clk_out = true;
             match self.cntmode {
                 TCA_MODE::SINGLESLOPE => {
                     // Increment counter
@@ -389,6 +415,7 @@ impl Clocked for Tca {
                         & (self.regs[TCA_CNTH] == self.regs[TCA_PERH])
                     {
                         self.regs[TCA_INTFLAGS] |= 0x01;
+                        self.ev_out |= 0x01;
                     }
 
                     // Compare match
@@ -398,6 +425,7 @@ impl Clocked for Tca {
                         {
                             self.regs[TCA_CTRLC] &= !(1 << i); // Clear WO
                             self.regs[TCA_INTFLAGS] |= 0x10 << i;
+                            self.ev_out |= 0x10 << i;
                         }
                     }
                 }
@@ -410,11 +438,29 @@ impl Clocked for Tca {
         for i in 0..3 {
             if (self.regs[TCA_CTRLB] & (0x10 << i)) != 0 {
                 // WO channel enabled
-                let wo = (self.regs[TCA_CTRLC] & (1 << i)) != 0;
-                if self.mux_alt[i] {
-                    self.port.borrow_mut().po_out(self.pins_alt[i], wo);
-                } else {
-                    self.port.borrow_mut().po_out(self.pins[i], wo);
+                self.wo_out(i, Some((self.regs[TCA_CTRLC] & (1 << i)) != 0));
+            }
+        }
+    }
+}
+
+impl EventGenerator for Tca {
+    fn event_state(&self, id: u8) -> bool {
+        // id corresponds to the bit position of the event in INTFLAGS
+        (self.ev_out & (1 << id)) != 0
+    }
+}
+
+impl PinMux for Tca {
+    fn route(&mut self, route: u8) {
+        // One bit per WO channel, WO3..5 are only used in split mode
+        for i in 0..3 {
+            let alt = (route & (1 << i)) != 0;
+            if alt != self.mux_alt[i] {
+                self.wo_out(i, None);
+                self.mux_alt[i] = alt;
+                if (self.regs[TCA_CTRLB] & (0x10 << i)) != 0 {
+                    self.wo_out(i, Some((self.regs[TCA_CTRLC] & (1 << i)) != 0));
                 }
             }
         }
@@ -1,6 +1,15 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use crate::memory::MemoryMapped;
+use crate::peripherals::slpctrl::SleepMode;
 use crate::peripherals::Clocked;
 use crate::peripherals::InterruptSource;
+use crate::peripherals::PinMux;
+use crate::peripherals::{EventGenerator, EventUser};
+
+use super::port::Port;
+use super::tca::Tca;
 
 const TCB_CTRLA: usize = 0x00;
 const TCB_CTRLB: usize = 0x01;
@@ -38,6 +47,12 @@ enum TCB_CLKSEL {
     EVENT,
 }
 
+const TCB_EV_CAPT: u8 = 0;
+const TCB_EV_COUNT: u8 = 1;
+
+const TCB_GEN_CAPT: u8 = 0;
+const TCB_GEN_OVF: u8 = 1;
+
 #[allow(dead_code)]
 pub struct Tcb {
     name: String,
@@ -46,10 +61,32 @@ pub struct Tcb {
     clksel: TCB_CLKSEL,
     cntmode: TCB_MODE,
     tictoc: bool,
+    port: Rc<RefCell<Port>>,
+    pin: u8,
+    port_alt: Rc<RefCell<Port>>,
+    pin_alt: u8,
+    mux_alt: bool,
+    tca: Rc<RefCell<Tca>>,
+    wo: bool,
+    running: bool,
+    capt_stage: u8,
+    ev_capt_state: bool,
+    ev_count_state: bool,
+    ev_rise: bool,
+    ev_fall: bool,
+    ev_count: bool,
+    ev_out: u8,
 }
 
 impl Tcb {
-    pub fn new(name: String) -> Self {
+    pub fn new(
+        name: String,
+        port: Rc<RefCell<Port>>,
+        pin: u8,
+        port_alt: Rc<RefCell<Port>>,
+        pin_alt: u8,
+        tca: Rc<RefCell<Tca>>,
+    ) -> Self {
         Tcb {
             name,
             regs: [0; 0x0E],
@@ -57,6 +94,248 @@ impl Tcb {
             clksel: TCB_CLKSEL::DIV1,
             cntmode: TCB_MODE::INT,
             tictoc: false,
+            port,
+            pin,
+            port_alt,
+            pin_alt,
+            mux_alt: false,
+            tca,
+            wo: false,
+            running: false,
+            capt_stage: 0,
+            ev_capt_state: false,
+            ev_count_state: false,
+            ev_rise: false,
+            ev_fall: false,
+            ev_count: false,
+            ev_out: 0,
+        }
+    }
+
+    fn cnt(&self) -> u16 {
+        ((self.regs[TCB_CNTH] as u16) << 8) | (self.regs[TCB_CNTL] as u16)
+    }
+
+    fn set_cnt(&mut self, value: u16) {
+        self.regs[TCB_CNTL] = value as u8;
+        self.regs[TCB_CNTH] = (value >> 8) as u8;
+    }
+
+    fn ccmp(&self) -> u16 {
+        ((self.regs[TCB_CCMPH] as u16) << 8) | (self.regs[TCB_CCMPL] as u16)
+    }
+
+    fn ccmpen(&self) -> bool {
+        (self.regs[TCB_CTRLB] & 0x10) != 0
+    }
+
+    fn ccmpinit(&self) -> bool {
+        (self.regs[TCB_CTRLB] & 0x20) != 0
+    }
+
+    fn is_async(&self) -> bool {
+        (self.regs[TCB_CTRLB] & 0x40) != 0
+    }
+
+    fn is_cascade(&self) -> bool {
+        (self.regs[TCB_CTRLA] & 0x20) != 0
+    }
+
+    fn is_capture_mode(&self) -> bool {
+        matches!(
+            self.cntmode,
+            TCB_MODE::CAPT | TCB_MODE::FRQ | TCB_MODE::PW | TCB_MODE::FRQPW
+        )
+    }
+
+    fn increment(&mut self) {
+        let (cnt, ovf) = self.cnt().overflowing_add(1);
+        self.set_cnt(cnt);
+        if ovf {
+            self.regs[TCB_INTFLAGS] |= 0x02;
+            self.ev_out |= 1 << TCB_GEN_OVF;
+        }
+    }
+
+    fn capture(&mut self) {
+        self.regs[TCB_CCMPL] = self.regs[TCB_CNTL];
+        self.regs[TCB_CCMPH] = self.regs[TCB_CNTH];
+        self.regs[TCB_INTFLAGS] |= 0x01;
+        self.ev_out |= 1 << TCB_GEN_CAPT;
+    }
+
+    fn handle_events(&mut self) {
+        let rise = self.ev_rise;
+        let fall = self.ev_fall;
+        self.ev_rise = false;
+        self.ev_fall = false;
+
+        // EDGE inverts the sense of the capture event in most modes
+        let (edge_pos, edge_neg) = if (self.regs[TCB_EVCTRL] & 0x10) != 0 {
+            (fall, rise)
+        } else {
+            (rise, fall)
+        };
+
+        match self.cntmode {
+            TCB_MODE::TIMEOUT => {
+                if edge_pos {
+                    self.set_cnt(0);
+                    self.running = true;
+                }
+                if edge_neg {
+                    self.running = false;
+                }
+            }
+            TCB_MODE::CAPT if edge_pos => self.capture(),
+            TCB_MODE::FRQ if edge_pos => {
+                self.capture();
+                self.set_cnt(0);
+            }
+            TCB_MODE::PW => {
+                if edge_pos {
+                    self.set_cnt(0);
+                }
+                if edge_neg {
+                    self.capture();
+                }
+            }
+            TCB_MODE::FRQPW => {
+                // Pulse width is captured to CCMP, counter stops at period
+                match self.capt_stage {
+                    0 => {
+                        if edge_pos {
+                            self.set_cnt(0);
+                            self.running = true;
+                            self.capt_stage = 1;
+                        }
+                    }
+                    1 => {
+                        if edge_neg {
+                            self.regs[TCB_CCMPL] = self.regs[TCB_CNTL];
+                            self.regs[TCB_CCMPH] = self.regs[TCB_CNTH];
+                            self.capt_stage = 2;
+                        }
+                    }
+                    2 => {
+                        if edge_pos {
+                            self.running = false;
+                            self.regs[TCB_INTFLAGS] |= 0x01;
+                            self.ev_out |= 1 << TCB_GEN_CAPT;
+                            self.capt_stage = 3;
+                        }
+                    }
+                    _ => {
+                        // Wait until the result has been collected
+                        if (self.regs[TCB_INTFLAGS] & 0x01) == 0 {
+                            self.capt_stage = 0;
+                        }
+                    }
+                }
+            }
+            TCB_MODE::SINGLE => {
+                // EDGE selects triggering on any edge
+                let trigger = if (self.regs[TCB_EVCTRL] & 0x10) != 0 {
+                    rise | fall
+                } else {
+                    rise
+                };
+                if trigger & !self.running {
+                    self.running = true;
+                    self.set_cnt(0);
+                    if self.is_async() {
+                        // Output responds to the event without waiting for CLK_TCB
+                        self.wo = true;
+                    }
+                }
+            }
+            _ => {} // Events not used in other modes
+        }
+    }
+
+    fn wo_out(&mut self, state: Option<bool>) {
+        let mut port = if self.mux_alt {
+            self.port_alt.borrow_mut()
+        } else {
+            self.port.borrow_mut()
+        };
+        let pin = if self.mux_alt { self.pin_alt } else { self.pin };
+        match state {
+            Some(wo) => port.po_out(pin, wo),
+            None => port.po_out_clear(pin),
+        }
+    }
+
+    fn count(&mut self) {
+        match self.cntmode {
+            TCB_MODE::INT => {
+                // Increment counter
+                let mut ovf;
+                (self.regs[TCB_CNTL], ovf) = self.regs[TCB_CNTL].overflowing_add(1);
+                if ovf {
+                    (self.regs[TCB_CNTH], ovf) = self.regs[TCB_CNTH].overflowing_add(1);
+                }
+                // Compare match
+                if (self.regs[TCB_CNTL] == self.regs[TCB_CCMPL])
+                    & (self.regs[TCB_CNTH] == self.regs[TCB_CCMPH])
+                {
+                    self.regs[TCB_INTFLAGS] |= 0x01;
+                    self.ev_out |= 1 << TCB_GEN_CAPT;
+                    // Reset counter
+                    // TODO: Is this correct or early by a cycle?
+                    self.regs[TCB_CNTL] = 0;
+                    self.regs[TCB_CNTH] = 0;
+                }
+                // Overflow
+                if ovf {
+                    self.regs[TCB_INTFLAGS] |= 0x02;
+                    self.ev_out |= 1 << TCB_GEN_OVF;
+                }
+            }
+            TCB_MODE::SINGLE if self.running => {
+                // Counter only runs once triggered, and halts at CCMP
+                if self.cnt() == self.ccmp() {
+                    self.wo = false;
+                    self.running = false;
+                    self.regs[TCB_INTFLAGS] |= 0x01;
+                    self.ev_out |= 1 << TCB_GEN_CAPT;
+                } else {
+                    self.wo = true;
+                    let (cnt, ovf) = self.cnt().overflowing_add(1);
+                    self.set_cnt(cnt);
+                    if ovf {
+                        self.regs[TCB_INTFLAGS] |= 0x02;
+                        self.ev_out |= 1 << TCB_GEN_OVF;
+                    }
+                }
+            }
+            TCB_MODE::TIMEOUT if self.running => {
+                self.increment();
+                if self.cnt() == self.ccmp() {
+                    self.regs[TCB_INTFLAGS] |= 0x01;
+                    self.ev_out |= 1 << TCB_GEN_CAPT;
+                }
+            }
+            TCB_MODE::CAPT | TCB_MODE::FRQ | TCB_MODE::PW => self.increment(),
+            TCB_MODE::FRQPW if self.running => self.increment(),
+            TCB_MODE::PWM8 => {
+                // CCMPL is the period, CCMPH the compare (duty cycle)
+                if self.regs[TCB_CNTL] == self.regs[TCB_CCMPL] {
+                    self.regs[TCB_CNTL] = 0;
+                    self.regs[TCB_INTFLAGS] |= 0x01;
+                    self.ev_out |= 1 << TCB_GEN_CAPT;
+                } else {
+                    self.regs[TCB_CNTL] += 1;
+                }
+                // Output set at BOTTOM, cleared on compare match
+                if self.regs[TCB_CNTL] == 0 {
+                    self.wo = true;
+                }
+                if self.regs[TCB_CNTL] == self.regs[TCB_CCMPH] {
+                    self.wo = false;
+                }
+            }
+            _ => {} // No other modes implemented
         }
     }
 }
@@ -71,6 +350,10 @@ impl MemoryMapped for Tcb {
             TCB_CTRLA..=TCB_TEMP => (self.regs[address], 0),
             TCB_CCMPL => {
                 self.regs[TCB_TEMP] = self.regs[TCB_CCMPH];
+                if self.is_capture_mode() {
+                    // Reading the captured value clears CAPT
+                    self.regs[TCB_INTFLAGS] &= !0x01;
+                }
                 (self.regs[TCB_CCMPL], 0)
             }
             TCB_CCMPH => (self.regs[TCB_TEMP], 0),
@@ -87,75 +370,53 @@ impl MemoryMapped for Tcb {
         match address {
             TCB_CTRLA => {
                 self.regs[TCB_CTRLA] = value;
-                self.enabled = (value & 0x01) != 0;
+                let enabled = (value & 0x01) != 0;
+                if enabled & !self.enabled {
+                    self.running = false;
+                    self.wo = self.ccmpinit();
+                }
+                self.enabled = enabled;
                 self.regs[TCB_STATUS] = if self.enabled { 1 } else { 0 };
                 self.clksel = match (value >> 1) & 0x07 {
                     0x00 => TCB_CLKSEL::DIV1,
                     0x01 => TCB_CLKSEL::DIV2,
-                    0x02 => {
-                        println!("[WARNING] Clock selection TCA0 is not implemented for TCB in this emulator.");
-                        TCB_CLKSEL::TCA0
-                    }
-                    0x07 => {
-                        println!("[WARNING] Clock selection EVENT is not implemented for TCB in this emulator.");
-                        TCB_CLKSEL::EVENT
-                    }
+                    0x02 => TCB_CLKSEL::TCA0,
+                    0x07 => TCB_CLKSEL::EVENT,
                     _ => TCB_CLKSEL::RESERVED,
                 };
-                if value & 0x70 != 0 {
-                    println!("[WARNING] RUNSTDBY/CASCADE/SYNCUPD features are not implemented for TCB in this emulator. These bits will be ignored.");
+                if value & 0x10 != 0 {
+                    println!("[WARNING] SYNCUPD feature is not implemented for TCB in this emulator. This bit will be ignored.");
                 }
             }
             TCB_CTRLB => {
                 self.regs[TCB_CTRLB] = value;
                 self.cntmode = match value & 0x07 {
                     0x00 => TCB_MODE::INT,
-                    0x01 => {
-                        println!(
-                            "[WARNING] TIMEOUT mode is not implemented for TCB in this emulator."
-                        );
-                        TCB_MODE::TIMEOUT
-                    }
-                    0x02 => {
-                        println!(
-                            "[WARNING] CAPT mode is not implemented for TCB in this emulator."
-                        );
-                        TCB_MODE::CAPT
-                    }
-                    0x03 => {
-                        println!("[WARNING] FRQ mode is not implemented for TCB in this emulator.");
-                        TCB_MODE::FRQ
-                    }
-                    0x04 => {
-                        println!("[WARNING] PW mode is not implemented for TCB in this emulator.");
-                        TCB_MODE::PW
-                    }
-                    0x05 => {
-                        println!(
-                            "[WARNING] FRQPW mode is not implemented for TCB in this emulator."
-                        );
-                        TCB_MODE::FRQPW
-                    }
-                    0x06 => {
-                        println!(
-                            "[WARNING] SINGLE mode is not implemented for TCB in this emulator."
-                        );
-                        TCB_MODE::SINGLE
-                    }
-                    0x07 => {
-                        println!(
-                            "[WARNING] PWM8 mode is not implemented for TCB in this emulator."
-                        );
-                        TCB_MODE::PWM8
-                    }
+                    0x01 => TCB_MODE::TIMEOUT,
+                    0x02 => TCB_MODE::CAPT,
+                    0x03 => TCB_MODE::FRQ,
+                    0x04 => TCB_MODE::PW,
+                    0x05 => TCB_MODE::FRQPW,
+                    0x06 => TCB_MODE::SINGLE,
+                    0x07 => TCB_MODE::PWM8,
                     _ => TCB_MODE::PWM8,
                 };
-                if value & 0x70 != 0 {
-                    println!("[WARNING] ASYNC/CCMPINIT/CCMPEN features are not implemented for TCB in this emulator. These bits will be ignored.");
+                self.running = false;
+                self.capt_stage = 0;
+                if self.ccmpen() {
+                    if !self.enabled {
+                        self.wo = self.ccmpinit();
+                    }
+                    let wo = self.wo;
+                    self.wo_out(Some(wo));
+                } else {
+                    self.wo_out(None);
                 }
             }
             TCB_EVCTRL => {
-                println!("[WARNING] EVECTRL features are not implemented for TCB in this emulator. This register will be ignored.");
+                if value & 0x40 != 0 {
+                    println!("[WARNING] FILTER feature is not implemented for TCB in this emulator. This bit will be ignored.");
+                }
                 self.regs[TCB_EVCTRL] = value;
             }
             TCB_DBGCTRL => {
@@ -187,42 +448,214 @@ impl InterruptSource for Tcb {
 }
 
 impl Clocked for Tcb {
+    fn sleep(&mut self, mode: Option<SleepMode>) -> bool {
+        match mode {
+            Some(SleepMode::Standby) => (self.regs[TCB_CTRLA] & 0x40) != 0, // RUNSTDBY
+            Some(SleepMode::PowerDown) => false,
+            _ => true,
+        }
+    }
+
     fn tick(&mut self, _time: u64) {
+        // Events are only asserted for a single cycle
+        self.ev_out = 0;
+
         // If not enabled we do nothing
         if self.enabled {
-            match self.clksel {
-                TCB_CLKSEL::DIV1 => {}
+            let clk = match self.clksel {
+                TCB_CLKSEL::DIV1 => true,
                 TCB_CLKSEL::DIV2 => {
                     self.tictoc = !self.tictoc;
-                    if self.tictoc {
-                        return;
-                    }; // Only continue every second tick
+                    !self.tictoc // Only continue every second tick
+                }
+                TCB_CLKSEL::TCA0 => self.tca.borrow().clk_out(),
+                TCB_CLKSEL::EVENT => self.ev_count,
+                TCB_CLKSEL::RESERVED => false,
+            };
+            self.ev_count = false;
+
+            // In cascade the count event from the lower TCB is applied before
+            // the capture event so that both halves are captured coherently
+            if self.is_cascade() {
+                if clk {
+                    self.count();
+                }
+                self.handle_events();
+            } else {
+                self.handle_events();
+                if clk {
+                    self.count();
                 }
-                _ => return, // No other clock modes implemented
             }
+        }
 
-            if let TCB_MODE::INT = self.cntmode {
-                // Increment counter
-                let mut ovf;
-                (self.regs[TCB_CNTL], ovf) = self.regs[TCB_CNTL].overflowing_add(1);
-                if ovf {
-                    (self.regs[TCB_CNTH], ovf) = self.regs[TCB_CNTH].overflowing_add(1);
+        // Port overrides
+        // We update pins regardless of whether TCB is enabled
+        if self.ccmpen() {
+            let wo = self.wo;
+            self.wo_out(Some(wo));
+        }
+    }
+}
+
+impl EventUser for Tcb {
+    fn event(&mut self, id: u8, state: bool) {
+        match id {
+            TCB_EV_CAPT => {
+                if ((self.regs[TCB_EVCTRL] & 0x01) != 0) & self.enabled {
+                    if state & !self.ev_capt_state {
+                        self.ev_rise = true;
+                    }
+                    if !state & self.ev_capt_state {
+                        self.ev_fall = true;
+                    }
                 }
-                // Compare match
-                if (self.regs[TCB_CNTL] == self.regs[TCB_CCMPL])
-                    & (self.regs[TCB_CNTH] == self.regs[TCB_CCMPH])
-                {
-                    self.regs[TCB_INTFLAGS] |= 0x01;
-                    // Reset counter
-                    // TODO: Is this correct or early by a cycle?
-                    self.regs[TCB_CNTL] = 0;
-                    self.regs[TCB_CNTH] = 0;
+                self.ev_capt_state = state;
+            }
+            TCB_EV_COUNT => {
+                if state & !self.ev_count_state & self.enabled {
+                    self.ev_count = true;
                 }
-                // Overflow
-                if ovf {
-                    self.regs[TCB_INTFLAGS] |= 0x02;
+                self.ev_count_state = state;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl EventGenerator for Tcb {
+    fn event_state(&self, id: u8) -> bool {
+        (self.ev_out & (1 << id)) != 0
+    }
+}
+
+impl PinMux for Tcb {
+    fn route(&mut self, route: u8) {
+        self.wo_out(None);
+        self.mux_alt = route != 0;
+        if self.ccmpen() {
+            self.wo_out(Some(self.wo));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nets::{Net, NetState};
+
+    const PIN_WO: u8 = 5;
+
+    fn setup() -> (Tcb, Rc<RefCell<Net>>) {
+        let port = Rc::new(RefCell::new(Port::new("PORTA".to_string())));
+        let net = Rc::new(RefCell::new(Net::new("WO".to_string())));
+        port.borrow_mut().connect(PIN_WO, Rc::clone(&net));
+        port.borrow_mut().write(0x00, 1 << PIN_WO); // DIR
+        let tca = Rc::new(RefCell::new(Tca::new(
+            "TCA0".to_string(),
+            Rc::clone(&port),
+            [0, 1, 2],
+            [3, 4, 5],
+        )));
+        let tcb = Tcb::new(
+            "TCB0".to_string(),
+            Rc::clone(&port),
+            PIN_WO,
+            Rc::clone(&port),
+            PIN_WO,
+            tca,
+        );
+        (tcb, net)
+    }
+
+    #[test]
+    fn pwm8_duty_cycle() {
+        // CCMPL is the period - 1, CCMPH the number of cycles WO is high
+        for (per, cmp) in [(9u8, 3u8), (99, 25), (254, 127)] {
+            let (mut tcb, net) = setup();
+            tcb.write(TCB_CCMPL, per);
+            tcb.write(TCB_CCMPH, cmp);
+            tcb.write(TCB_CTRLB, 0x17); // CCMPEN, PWM8
+            tcb.write(TCB_CTRLA, 0x01); // ENABLE
+                                        // WO is first set at BOTTOM, so the first period is skipped
+            let period = usize::from(per) + 1;
+            let mut high = 0;
+            for i in 0..11 * period {
+                tcb.tick(0);
+                net.borrow_mut().update(0);
+                if (i >= period) & (net.borrow().state == NetState::High) {
+                    high += 1;
+                }
+            }
+            assert_eq!(high, 10 * usize::from(cmp), "CCMPL {per}, CCMPH {cmp}");
+        }
+    }
+
+    // Feeds a capture event of the given high and low times, in cycles
+    fn square(tcb: &mut Tcb, high: usize, low: usize, periods: usize) {
+        for _ in 0..periods {
+            for level in [true, false] {
+                for _ in 0..if level { high } else { low } {
+                    tcb.event(TCB_EV_CAPT, level);
+                    tcb.tick(0);
                 }
             }
         }
     }
+
+    fn read16(tcb: &mut Tcb, address: usize) -> u16 {
+        u16::from_le_bytes([tcb.read(address).0, tcb.read(address + 1).0])
+    }
+
+    #[test]
+    fn capture_modes() {
+        // CNTMODE, captured value, counter after the measurement
+        let cases = [
+            (0x03, 100, None),     // FRQ, period
+            (0x04, 30, None),      // PW, high time
+            (0x05, 30, Some(100)), // FRQPW, high time then period
+        ];
+        for (mode, ccmp, cnt) in cases {
+            let (mut tcb, _) = setup();
+            tcb.write(TCB_EVCTRL, 0x01); // CAPTEI
+            tcb.write(TCB_CTRLB, mode);
+            tcb.write(TCB_CTRLA, 0x01); // ENABLE
+            square(&mut tcb, 30, 70, 3);
+            assert_eq!(tcb.read(TCB_INTFLAGS).0 & 0x01, 0x01, "mode {mode}");
+            if let Some(cnt) = cnt {
+                assert_eq!(read16(&mut tcb, TCB_CNTL), cnt, "mode {mode}");
+            }
+            assert_eq!(read16(&mut tcb, TCB_CCMPL), ccmp, "mode {mode}");
+            assert_eq!(tcb.read(TCB_INTFLAGS).0 & 0x01, 0x00, "mode {mode}");
+        }
+    }
+
+    #[test]
+    fn cascade_capture() {
+        // TCB0 counts CLK_PER, TCB1 counts TCB0 overflows, both capture the same
+        // event. Captures either side of a TCB0 overflow must not tear.
+        for capture in [1000, 65533, 65534, 65535, 65536, 65537, 131071, 131072] {
+            let (mut low, _) = setup();
+            let (mut high, _) = setup();
+            low.write(TCB_EVCTRL, 0x01); // CAPTEI
+            low.write(TCB_CTRLB, 0x02); // CAPT
+            low.write(TCB_CTRLA, 0x01); // ENABLE
+            high.write(TCB_EVCTRL, 0x01);
+            high.write(TCB_CTRLB, 0x02);
+            high.write(TCB_CTRLA, 0x2F); // CASCADE, CLKSEL EVENT, ENABLE
+            for cycle in 0..=capture + 2 {
+                low.tick(0);
+                high.tick(0);
+                // EVSYS routes events after the generators have been clocked
+                let ovf = low.event_state(TCB_GEN_OVF);
+                high.event(TCB_EV_COUNT, ovf);
+                low.event(TCB_EV_CAPT, cycle == capture);
+                high.event(TCB_EV_CAPT, cycle == capture);
+            }
+            let value = (u32::from(read16(&mut high, TCB_CCMPL)) << 16)
+                | u32::from(read16(&mut low, TCB_CCMPL));
+            // The event is generated in cycle `capture` and captured in the next
+            assert_eq!(value, capture + 1, "capture at {capture}");
+        }
+    }
 }
@@ -3,8 +3,10 @@ use std::collections::VecDeque;
 use std::rc::Rc;
 
 use crate::memory::MemoryMapped;
+use crate::peripherals::slpctrl::SleepMode;
 use crate::peripherals::Clocked;
 use crate::peripherals::InterruptSource;
+use crate::peripherals::PinMux;
 
 use super::port::Port;
 
@@ -24,7 +26,13 @@ const USART_EVCTRL: usize = 0x0C;
 const USART_TXPLCTRL: usize = 0x0D;
 const USART_RXPLCTRL: usize = 0x0E;
 
+const USART_PIN_RXD: usize = 0;
+const USART_PIN_TXD: usize = 1;
+const USART_PIN_XCK: usize = 2;
+const USART_PIN_XDIR: usize = 3;
+
 #[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq)]
 enum UsartMode {
     NORMAL,
     CLK2X,
@@ -32,6 +40,31 @@ enum UsartMode {
     LINAUTO,
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq)]
+enum UsartCmode {
+    ASYNC,
+    SYNC,
+    IRCOM,
+    MSPI,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq)]
+enum Rs485Mode {
+    OFF,
+    EXT,
+    INT,
+}
+
+#[derive(PartialEq)]
+enum AutoBaud {
+    Idle,
+    Break,
+    SyncWait,
+    Sync,
+}
+
 #[derive(Debug, PartialEq)]
 enum RxState {
     High,
@@ -53,7 +86,9 @@ pub struct Usart {
     port_alt: Rc<RefCell<Port>>,
     pins: [u8; 4],
     pins_alt: [u8; 4],
-    pub mux_alt: bool,
+    mux: u8,
+    tx_level: bool,
+    tx_drive: bool,
     rx_state: UsartState,
     tx_state: UsartState,
     rx_accum: u32,
@@ -65,6 +100,22 @@ pub struct Usart {
     tx_reg: u16,
     rx_buf: VecDeque<u16>,
     tx_buf: VecDeque<u16>,
+    rx_pending: Option<u16>,
+    rx_overflow: bool,
+    xck_level: bool,
+    xck_count: u32,
+    xck_edge: u8,
+    ir_tx_pulse: u32,
+    ir_rx_low: u32,
+    ir_rx_hold: u32,
+    ab_state: AutoBaud,
+    ab_low: u32,
+    ab_count: u32,
+    ab_last: u32,
+    ab_edges: u8,
+    ab_min: u32,
+    ab_max: u32,
+    standby: bool,
 }
 
 impl Usart {
@@ -82,9 +133,11 @@ impl Usart {
             port_alt,
             pins,
             pins_alt,
-            mux_alt: false,
+            mux: 0,
+            tx_level: true,
+            tx_drive: false,
             rx_state: UsartState::Idle,
-            tx_state: UsartState::Shift,
+            tx_state: UsartState::Idle,
             rx_accum: 0,
             tx_accum: 0,
             rx_pinstate: RxState::Undefined,
@@ -94,11 +147,132 @@ impl Usart {
             tx_reg: 0,
             rx_buf: VecDeque::new(),
             tx_buf: VecDeque::new(),
+            rx_pending: None,
+            rx_overflow: false,
+            xck_level: false,
+            xck_count: 0,
+            xck_edge: 0,
+            ir_tx_pulse: 0,
+            ir_rx_low: 0,
+            ir_rx_hold: 0,
+            ab_state: AutoBaud::Idle,
+            ab_low: 0,
+            ab_count: 0,
+            ab_last: 0,
+            ab_edges: 0,
+            ab_min: 0,
+            ab_max: 0,
+            standby: false,
         };
         usart.regs[USART_STATUS] = 0x20;
+        usart.regs[USART_CTRLC] = 0x03; // 8N1
         usart
     }
 
+    fn pin(&self, index: usize) -> Option<(&Rc<RefCell<Port>>, u8)> {
+        match self.mux {
+            0 => Some((&self.port, self.pins[index])),
+            1 => Some((&self.port_alt, self.pins_alt[index])),
+            _ => None, // NONE
+        }
+    }
+
+    fn po_out(&self, index: usize, state: Option<bool>) {
+        if let Some((port, pin)) = self.pin(index) {
+            match state {
+                Some(level) => port.borrow_mut().po_out(pin, level),
+                None => port.borrow_mut().po_out_clear(pin),
+            }
+        }
+    }
+
+    fn po_dir(&self, index: usize, state: Option<bool>) {
+        if let Some((port, pin)) = self.pin(index) {
+            match state {
+                Some(output) => port.borrow_mut().po_dir(pin, output),
+                None => port.borrow_mut().po_dir_clear(pin),
+            }
+        }
+    }
+
+    fn txd(&mut self, state: Option<bool>) {
+        if let Some(level) = state {
+            self.tx_level = level;
+        }
+        self.po_out(USART_PIN_TXD, state);
+
+        // Open-drain only drives low, and RS-485 INT only drives while transmitting
+        let dir = match state {
+            Some(level) => {
+                let mut output = if self.is_odme() { Some(!level) } else { None };
+                if self.rs485().eq(&Rs485Mode::INT) {
+                    output = Some(output.unwrap_or(true) & self.tx_drive);
+                }
+                output
+            }
+            None => None,
+        };
+        self.po_dir(USART_PIN_TXD, dir);
+    }
+
+    fn drive(&mut self, active: bool) {
+        // Driver enable for RS-485, asserted on XDIR while transmitting
+        self.tx_drive = active;
+        if self.rs485().eq(&Rs485Mode::EXT) {
+            self.po_out(USART_PIN_XDIR, Some(active));
+        }
+        if self.txen() {
+            self.txd(Some(self.tx_level));
+        }
+    }
+
+    fn tx_complete(&mut self) {
+        self.tx_state = UsartState::Idle;
+        self.regs[USART_STATUS] |= 0x40; // Set TXCIF (buffer empty)
+        self.drive(false);
+    }
+
+    fn xck(&mut self, state: Option<bool>) {
+        // XCK polarity is selected by INVEN on the pin, so is not applied here
+        if let Some(level) = state {
+            self.xck_level = level;
+        }
+        self.po_out(USART_PIN_XCK, state);
+    }
+
+    fn rxd(&self) -> bool {
+        // Loop-back connects the receiver to the TXD pin instead of RXD
+        let index = if self.is_lbme() {
+            USART_PIN_TXD
+        } else {
+            USART_PIN_RXD
+        };
+        match self.pin(index) {
+            Some((port, pin)) => port.borrow().get_pinstate(pin),
+            None => true, // Not connected, idle
+        }
+    }
+
+    fn is_lbme(&self) -> bool {
+        (self.regs[USART_CTRLA] & 0x08) != 0
+    }
+
+    fn rs485(&self) -> Rs485Mode {
+        match self.regs[USART_CTRLA] & 0x03 {
+            1 => Rs485Mode::EXT,
+            2 => Rs485Mode::INT,
+            _ => Rs485Mode::OFF,
+        }
+    }
+
+    fn is_sfden(&self) -> bool {
+        (self.regs[USART_CTRLB] & 0x10) != 0
+    }
+
+    fn is_odme(&self) -> bool {
+        (self.regs[USART_CTRLB] & 0x08) != 0
+    }
+
     fn rxen(&self) -> bool {
         (self.regs[USART_CTRLB] & 0x80) != 0
     }
@@ -120,9 +294,8 @@ impl Usart {
     fn baud_inc(&self) -> u32 {
         let mut baud = ((self.regs[USART_BAUDH] as u64) << 8) | (self.regs[USART_BAUDL] as u64);
         match self.mode() {
-            UsartMode::NORMAL => baud *= 16,
             UsartMode::CLK2X => baud *= 8,
-            _ => {}
+            _ => baud *= 16,
         }
         let inc = (0x100000000u64 * 64) / baud.max(64); // note tick = clk_per so cancels
         inc as u32
@@ -131,6 +304,440 @@ impl Usart {
     fn dre(&self) -> bool {
         (self.regs[USART_STATUS] & 0x20) != 0
     }
+
+    fn cmode(&self) -> UsartCmode {
+        match self.regs[USART_CTRLC] >> 6 {
+            0 => UsartCmode::ASYNC,
+            1 => UsartCmode::SYNC,
+            2 => UsartCmode::IRCOM,
+            _ => UsartCmode::MSPI,
+        }
+    }
+
+    fn xck_host(&self) -> bool {
+        // The XCK pin direction selects clock host or client in synchronous mode
+        match self.cmode() {
+            UsartCmode::MSPI => true,
+            UsartCmode::SYNC => match self.pin(USART_PIN_XCK) {
+                Some((port, pin)) => port.borrow().get_dir(pin),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn xck_half(&self) -> u32 {
+        // Synchronous baud rate is fCLK_PER / (2 * BAUD[15:6])
+        let baud = ((self.regs[USART_BAUDH] as u32) << 8) | (self.regs[USART_BAUDL] as u32);
+        (baud >> 6).max(1)
+    }
+
+    fn is_udord(&self) -> bool {
+        (self.regs[USART_CTRLC] & 0x04) != 0
+    }
+
+    fn is_ucpha(&self) -> bool {
+        (self.regs[USART_CTRLC] & 0x02) != 0
+    }
+
+    fn cycles_per_bit(&self) -> u32 {
+        ((1u64 << 32) / self.baud_inc().max(1) as u64) as u32
+    }
+
+    fn chsize(&self) -> usize {
+        match self.regs[USART_CTRLC] & 0x07 {
+            size @ 0..=3 => 5 + size as usize,
+            6 | 7 => 9,
+            _ => 8, // Reserved
+        }
+    }
+
+    fn is_9bitl(&self) -> bool {
+        (self.regs[USART_CTRLC] & 0x07) == 0x06
+    }
+
+    fn parity(&self) -> Option<bool> {
+        // Returns Some(odd) if parity is enabled
+        match (self.regs[USART_CTRLC] >> 4) & 0x03 {
+            2 => Some(false),
+            3 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn parity_bit(data: u16, odd: bool) -> bool {
+        (data.count_ones() & 1 == 1) != odd
+    }
+
+    fn stop_bits(&self) -> usize {
+        1 + ((self.regs[USART_CTRLC] >> 3) & 0x01) as usize
+    }
+
+    fn frame(&self, data: u16) -> (u16, usize) {
+        // Returns the frame bits (LSB first, including the start bit) and the frame length
+        let n = self.chsize();
+        let data = data & ((1 << n) - 1);
+        let mut frame = data << 1;
+        let mut len = 1 + n;
+        if let Some(odd) = self.parity() {
+            frame |= (Self::parity_bit(data, odd) as u16) << len;
+            len += 1;
+        }
+        for _ in 0..self.stop_bits() {
+            frame |= 1 << len;
+            len += 1;
+        }
+        (frame, len)
+    }
+
+    fn transmit(&mut self) {
+        if !self.txen() {
+            return;
+        }
+        let data =
+            ((self.regs[USART_TXDATAH] as u16 & 0x01) << 8) | self.regs[USART_TXDATAL] as u16;
+        if self.tx_state.eq(&UsartState::Idle) {
+            match self.cmode() {
+                UsartCmode::ASYNC | UsartCmode::IRCOM => {
+                    let (mut frame, mut len) = self.frame(data);
+                    if self.rs485().ne(&Rs485Mode::OFF) {
+                        // One bit of guard time between driver enable and the start bit
+                        frame = (frame << 1) | 1;
+                        len += 1;
+                    }
+                    self.drive(true);
+                    self.tx_out((frame & 1) == 1);
+                    self.tx_reg = frame >> 1;
+                    self.tx_bit = len - 1;
+                    self.tx_accum = 0;
+                }
+                UsartCmode::SYNC => {
+                    // Bits are shifted out on the rising edges of XCK
+                    (self.tx_reg, self.tx_bit) = self.frame(data);
+                    self.drive(true);
+                    self.xck_count = self.xck_half();
+                }
+                UsartCmode::MSPI => {
+                    self.drive(true);
+                    self.mspi_load(data);
+                    self.xck_count = self.xck_half();
+                }
+            }
+            if self.xck_host() {
+                self.xck(Some(false));
+            }
+            self.tx_state = UsartState::Shift;
+        } else {
+            self.tx_buf.push_back(data);
+            self.regs[USART_STATUS] &= 0xDF; // Clear DREIF
+        }
+    }
+
+    fn receive(&mut self) {
+        // Decode the frame held in rx_reg (LSB first, including the start bit)
+        let n = self.chsize();
+        let data = (self.rx_reg >> 1) & ((1 << n) - 1);
+        let mut index = 1 + n;
+        let mut perr = 0u16;
+        if let Some(odd) = self.parity() {
+            if ((self.rx_reg >> index) & 1 == 1) != Self::parity_bit(data, odd) {
+                perr = 1 << 9;
+            }
+            index += 1;
+        }
+        // Only the first stop bit is checked by the receiver
+        let ferr = if (self.rx_reg >> index) & 1 == 0 {
+            1 << 10
+        } else {
+            0u16
+        };
+        self.rx_push(ferr | perr | data);
+    }
+
+    fn rx_start(&mut self) {
+        self.rx_state = UsartState::Shift;
+        self.rx_bit = 0;
+        self.rx_reg = 0;
+        if self.rx_pending.take().is_some() {
+            // Waiting frame is overwritten by the new one
+            self.rx_overflow = true;
+        }
+    }
+
+    fn rx_push(&mut self, entry: u16) {
+        let mut entry = entry;
+        if self.rx_overflow | (self.rx_buf.len() > 1 && self.rx_pending.is_some()) {
+            entry |= 1 << 14; // BUFOVF
+        }
+        self.rx_overflow = false;
+        if self.rx_buf.len() < 2 {
+            self.rx_buf.push_back(entry);
+        } else {
+            // FIFO full, frame waits in the shift register
+            self.rx_pending = Some(entry);
+        }
+        self.regs[USART_RXDATAH] |= 0x80; // Set RXCIF (unread data)
+        self.regs[USART_STATUS] |= 0x80; // Set RXCIF (unread data)
+    }
+
+    fn rx_frame_len(&self) -> usize {
+        // start + data + parity + first stop bit
+        2 + self.chsize() + self.parity().is_some() as usize
+    }
+
+    fn tx_out(&mut self, level: bool) {
+        // IrDA encodes zeros as a pulse, of 3/16 of a bit or TXPL cycles
+        if self.cmode().eq(&UsartCmode::IRCOM) & (self.regs[USART_TXPLCTRL] != 0xFF) & !level {
+            self.txd(Some(false));
+            self.ir_tx_pulse = match self.regs[USART_TXPLCTRL] {
+                0 => (self.cycles_per_bit() * 3 / 16).max(1),
+                cycles => cycles as u32,
+            };
+        } else {
+            self.ir_tx_pulse = 0;
+            self.txd(Some(level));
+        }
+    }
+
+    fn ir_decode(&mut self, level: bool) -> bool {
+        // A low pulse of at least RXPL + 1 cycles is decoded as a zero bit
+        let threshold = (self.regs[USART_RXPLCTRL] & 0x7F) as u32 + 1;
+        if level {
+            self.ir_rx_low = 0;
+        } else {
+            self.ir_rx_low += 1;
+            if self.ir_rx_low == threshold {
+                self.ir_rx_hold = self.cycles_per_bit();
+            }
+        }
+        if self.ir_rx_hold > 0 {
+            self.ir_rx_hold -= 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    fn autobaud(&mut self, level: bool) -> bool {
+        // Returns true while the receiver should ignore the line
+        let falling = !level & self.rx_pinstate.eq(&RxState::High);
+        let rising = level & self.rx_pinstate.eq(&RxState::Low);
+        let wfb = self.mode().eq(&UsartMode::GENAUTO) & ((self.regs[USART_STATUS] & 0x01) != 0);
+
+        match self.ab_state {
+            AutoBaud::Idle => {
+                self.ab_low = if level { 0 } else { self.ab_low + 1 };
+                // A break of any length is accepted while waiting for break
+                if (wfb & falling) | (self.ab_low >= 12 * self.cycles_per_bit()) {
+                    self.ab_state = AutoBaud::Break;
+                }
+            }
+            AutoBaud::Break => {
+                if rising {
+                    self.ab_state = AutoBaud::SyncWait;
+                }
+            }
+            AutoBaud::SyncWait => {
+                if falling {
+                    self.ab_state = AutoBaud::Sync;
+                    self.ab_count = 0;
+                    self.ab_last = 0;
+                    self.ab_edges = 0;
+                    self.ab_min = u32::MAX;
+                    self.ab_max = 0;
+                }
+            }
+            AutoBaud::Sync => {
+                self.ab_count += 1;
+                self.ab_last += 1;
+                if rising | falling {
+                    self.ab_min = self.ab_min.min(self.ab_last);
+                    self.ab_max = self.ab_max.max(self.ab_last);
+                    self.ab_last = 0;
+                    self.ab_edges += 1;
+                }
+                if self.ab_edges == 8 {
+                    // Sync field 0x55 has a falling edge eight bits after the start bit,
+                    // which must not be mistaken for a start bit by the receiver
+                    self.sync_field();
+                    return true;
+                }
+            }
+        }
+        wfb | self.ab_state.ne(&AutoBaud::Idle)
+    }
+
+    fn sync_field(&mut self) {
+        self.ab_state = AutoBaud::Idle;
+        self.ab_low = 0;
+        let baud = self.ab_count / 2; // BAUD = 64 * fCLK_PER / (16 * fBAUD)
+        let bit = self.ab_count / 8;
+        let consistent = (self.ab_min > bit / 2) & (self.ab_max < bit + bit / 2);
+        let valid = match self.mode() {
+            UsartMode::LINAUTO => consistent & (0x64..=0xFFFF).contains(&baud),
+            _ => (0x64..=0xFFFF).contains(&baud),
+        };
+        if valid {
+            self.regs[USART_BAUDL] = baud as u8;
+            self.regs[USART_BAUDH] = (baud >> 8) as u8;
+            self.regs[USART_STATUS] |= 0x02; // Set BDF
+            self.regs[USART_STATUS] &= 0xFE; // Clear WFB
+        } else {
+            self.regs[USART_STATUS] |= 0x08; // Set ISFIF
+        }
+    }
+
+    fn sync_rising(&mut self) -> bool {
+        // Returns false if there was nothing left to transmit
+        if !self.txen() | self.tx_state.eq(&UsartState::Idle) {
+            return false;
+        }
+        if self.tx_bit == 0 {
+            match self.tx_buf.pop_front() {
+                Some(data) => {
+                    (self.tx_reg, self.tx_bit) = self.frame(data);
+                    self.regs[USART_STATUS] |= 0x20; // Set DREIF
+                }
+                None => {
+                    self.tx_complete();
+                    return false;
+                }
+            }
+        }
+        self.txd(Some((self.tx_reg & 1) == 1));
+        self.tx_reg >>= 1;
+        self.tx_bit -= 1;
+        true
+    }
+
+    fn sync_falling(&mut self) {
+        if !self.rxen() {
+            return;
+        }
+        let level = self.rxd();
+        match self.rx_state {
+            UsartState::Idle => {
+                if !level {
+                    self.rx_start();
+                    self.rx_bit = 1;
+                }
+            }
+            UsartState::Shift => {
+                if level {
+                    self.rx_reg |= 1 << self.rx_bit;
+                }
+                self.rx_bit += 1;
+                if self.rx_bit == self.rx_frame_len() {
+                    self.rx_state = UsartState::Idle;
+                    self.receive();
+                }
+            }
+        }
+    }
+
+    fn mspi_load(&mut self, data: u16) {
+        // Bits are always shifted out from the LSB of tx_reg
+        self.tx_reg = if self.is_udord() {
+            data & 0xFF
+        } else {
+            ((data as u8).reverse_bits()) as u16
+        };
+        self.rx_reg = 0;
+        self.xck_edge = 0;
+        if !self.is_ucpha() {
+            // First bit must be valid before the first leading edge
+            self.txd(Some((self.tx_reg & 1) == 1));
+        }
+    }
+
+    fn mspi_edge(&mut self) {
+        self.xck_edge += 1;
+        let leading = (self.xck_edge & 1) == 1;
+        self.xck(Some(leading));
+        let index = (self.xck_edge - 1) >> 1;
+        if leading != self.is_ucpha() {
+            // Sample
+            if self.rxd() {
+                self.rx_reg |= 1 << index;
+            }
+        } else if self.xck_edge < 16 {
+            self.txd(Some(((self.tx_reg >> (self.xck_edge >> 1)) & 1) == 1));
+        }
+        if self.xck_edge == 16 {
+            if self.rxen() {
+                let data = if self.is_udord() {
+                    self.rx_reg & 0xFF
+                } else {
+                    ((self.rx_reg as u8).reverse_bits()) as u16
+                };
+                self.rx_push(data);
+            }
+            match self.tx_buf.pop_front() {
+                Some(data) => {
+                    self.mspi_load(data);
+                    self.regs[USART_STATUS] |= 0x20; // Set DREIF
+                }
+                None => self.tx_complete(),
+            }
+        }
+    }
+
+    fn sync_tick(&mut self) {
+        if self.xck_host() {
+            if !self.txen() | self.tx_state.eq(&UsartState::Idle) {
+                return;
+            }
+            self.xck_count -= 1;
+            if self.xck_count > 0 {
+                return;
+            }
+            self.xck_count = self.xck_half();
+            if self.cmode().eq(&UsartCmode::MSPI) {
+                self.mspi_edge();
+            } else if self.xck_level {
+                self.xck(Some(false));
+                self.sync_falling();
+            } else if self.sync_rising() {
+                self.xck(Some(true));
+            }
+        } else {
+            // Client, clocked by edges on the XCK pin
+            let level = match self.pin(USART_PIN_XCK) {
+                Some((port, pin)) => port.borrow().get_pinstate(pin),
+                None => false,
+            };
+            if level != self.xck_level {
+                self.xck_level = level;
+                if level {
+                    self.sync_rising();
+                } else {
+                    self.sync_falling();
+                }
+            }
+        }
+    }
+
+    fn rx_peek(&mut self) {
+        if let Some(&data) = self.rx_buf.front() {
+            self.regs[USART_RXDATAL] = data as u8;
+            self.regs[USART_RXDATAH] &= 0x80;
+            self.regs[USART_RXDATAH] |= ((data >> 8) as u8) & 0x47;
+        }
+    }
+
+    fn rx_pop(&mut self) {
+        if !self.rx_buf.is_empty() {
+            self.rx_peek();
+            self.rx_buf.pop_front();
+            if let Some(data) = self.rx_pending.take() {
+                self.rx_buf.push_back(data);
+            }
+            if self.rx_buf.is_empty() {
+                self.regs[USART_RXDATAH] &= 0x7F; // Clear RXCIF (buffer empty)
+                self.regs[USART_STATUS] &= 0x7F; // Clear RXCIF (buffer empty)
+            }
+        }
+    }
 }
 
 impl MemoryMapped for Usart {
@@ -141,29 +748,23 @@ impl MemoryMapped for Usart {
     fn read(&mut self, address: usize) -> (u8, usize) {
         match address {
             USART_RXDATAL => {
-                // This logic only works for < 9 bit frames
-                if !self.rx_buf.is_empty() {
-                    let data = self.rx_buf.pop_front().unwrap();
-                    self.regs[USART_RXDATAL] = data as u8;
-                    self.regs[USART_RXDATAH] &= 0xC0;
-                    self.regs[USART_RXDATAH] |= (data >> 8) as u8;
-                    if self.rx_buf.is_empty() {
-                        self.regs[USART_RXDATAH] &= 0x7F; // Clear RXCIF (buffer empty)
-                        self.regs[USART_STATUS] &= 0x7F; // Clear RXCIF (buffer empty)
-                    }
+                // In 9BITL mode RXDATAH is read last, and pops the FIFO
+                if self.is_9bitl() {
+                    self.rx_peek();
+                } else {
+                    self.rx_pop();
                 }
                 (self.regs[USART_RXDATAL], 0)
             }
             USART_RXDATAH => {
-                // This logic only works for < 9 bit frames
-                if !self.rx_buf.is_empty() {
-                    let data = self.rx_buf.front().unwrap();
-                    self.regs[USART_RXDATAH] &= 0xC0;
-                    self.regs[USART_RXDATAH] |= (data >> 8) as u8;
+                if self.is_9bitl() {
+                    self.rx_pop();
+                } else {
+                    self.rx_peek();
                 }
                 (self.regs[USART_RXDATAH], 0)
             }
-            USART_RXDATAL..=USART_RXPLCTRL => (self.regs[address], 0),
+            USART_TXDATAL..=USART_RXPLCTRL => (self.regs[address], 0),
             _ => (0, 0),
         }
     }
@@ -172,29 +773,17 @@ impl MemoryMapped for Usart {
         match address {
             // RXDATA is read only
             USART_TXDATAL => {
-                if self.dre() {
-                    if self.tx_state.eq(&UsartState::Idle) {
-                        // start bit
-                        if self.mux_alt {
-                            self.port_alt.borrow_mut().po_out(self.pins_alt[1], false);
-                        } else {
-                            self.port.borrow_mut().po_out(self.pins[1], false);
-                        }
-                        self.tx_bit = 9; // check??
-                        self.tx_reg = (value as u16) | 0x0100; // Add stop bit
-                        self.tx_accum = 0;
-                        self.tx_state = UsartState::Shift;
-                    } else {
-                        self.tx_buf.push_back(value as u16);
-                        self.regs[USART_TXDATAL] = value;
-                        if self.tx_buf.len() > 1 {
-                            self.regs[USART_STATUS] &= 0xDF; // Clear DREIF
-                        }
-                    }
+                // In 9BITL mode TXDATAH is written last, and starts the transmission
+                self.regs[USART_TXDATAL] = value;
+                if self.dre() & !self.is_9bitl() {
+                    self.transmit();
                 }
             }
             USART_TXDATAH => {
-                println!("[WARNING] 9-bit mode is not implemented for USART in this emulator. These bits will be ignored.");
+                self.regs[USART_TXDATAH] = value & 0x01;
+                if self.dre() & self.is_9bitl() {
+                    self.transmit();
+                }
             }
             USART_STATUS => {
                 self.regs[USART_STATUS] &= !value | 0xA5; // Flags cleared by writing 1
@@ -205,33 +794,53 @@ impl MemoryMapped for Usart {
                 }
             }
             USART_CTRLA => {
+                if ((self.regs[USART_CTRLA] ^ value) & 0x03) != 0 {
+                    self.po_out(USART_PIN_XDIR, None);
+                }
                 self.regs[USART_CTRLA] = value;
-                if (value & 0x0F) != 0 {
-                    println!("[WARNING] LBME, ABEIE and RS485 features are not implemented for USART in this emulator. These bits will be ignored.");
+                if self.rs485().eq(&Rs485Mode::EXT) {
+                    self.po_out(USART_PIN_XDIR, Some(self.tx_drive));
+                }
+                if self.txen() {
+                    self.txd(Some(self.tx_level));
+                }
+                if (value & 0x03) == 0x03 {
+                    println!("[WARNING] Reserved RS485 value written to USART CTRLA. RS-485 mode will be disabled.");
                 }
             }
             USART_CTRLB => {
                 self.regs[USART_CTRLB] = value;
-                // TODO: This won't behave properly if mux is changed after config
                 if self.txen() {
-                    if self.mux_alt {
-                        self.port_alt.borrow_mut().po_out(self.pins_alt[1], true);
-                    } else {
-                        self.port.borrow_mut().po_out(self.pins[1], true);
-                    }
-                } else if self.mux_alt {
-                    self.port_alt.borrow_mut().po_out_clear(self.pins_alt[1]);
+                    self.txd(Some(true));
                 } else {
-                    self.port.borrow_mut().po_out_clear(self.pins[1]);
+                    self.txd(None);
                 }
-                if (value & 0x1D) != 0 {
-                    println!("[WARNING] SFDEN, ODME, GENAUTO, LINAUTO and MPCM features are not implemented for USART in this emulator. These bits will be ignored.");
+                if (value & 0x01) != 0 {
+                    println!("[WARNING] MPCM feature is not implemented for USART in this emulator. This bit will be ignored.");
                 }
             }
             USART_CTRLC => {
+                if ((self.regs[USART_CTRLC] ^ value) & 0xC0) != 0 {
+                    // Communication mode changed, abandon any transfer
+                    self.xck(None);
+                    self.xck_level = false;
+                    self.tx_state = UsartState::Idle;
+                    self.rx_state = UsartState::Idle;
+                    self.ir_tx_pulse = 0;
+                    self.ir_rx_hold = 0;
+                    if self.txen() {
+                        self.txd(Some(true));
+                    }
+                }
                 self.regs[USART_CTRLC] = value;
-                if value != 0x03 {
-                    println!("[WARNING] Only asynchronous 8N1 mode is implemented for USART in this emulator. These bits will be ignored.");
+                // In MSPI mode UDORD and UCPHA replace the frame format bits
+                if self.cmode().ne(&UsartCmode::MSPI) {
+                    if (value & 0x30) == 0x10 {
+                        println!("[WARNING] Reserved PMODE value written to USART CTRLC. Parity will be disabled.");
+                    }
+                    if (value & 0x06) == 0x04 {
+                        println!("[WARNING] Reserved CHSIZE value written to USART CTRLC. 8-bit frames will be used.");
+                    }
                 }
             }
             USART_BAUDL..=USART_BAUDH => {
@@ -250,12 +859,10 @@ impl MemoryMapped for Usart {
                 self.regs[USART_EVCTRL] = value;
             }
             USART_TXPLCTRL => {
-                println!("[WARNING] TXPLCTRL features are not implemented for USART in this emulator. This register will be ignored.");
                 self.regs[USART_TXPLCTRL] = value;
             }
             USART_RXPLCTRL => {
-                println!("[WARNING] RXPLCTRL features are not implemented for USART in this emulator. This register will be ignored.");
-                self.regs[USART_RXPLCTRL] = value;
+                self.regs[USART_RXPLCTRL] = value & 0x7F;
             }
             _ => {}
         }
@@ -265,18 +872,48 @@ impl MemoryMapped for Usart {
 
 impl InterruptSource for Usart {
     fn interrupt(&mut self, mask: u8) -> bool {
-        (self.regs[USART_STATUS] & self.regs[USART_CTRLA] & mask) != 0x00
+        // ISFIF is enabled by ABEIE, the other flags line up with their enables
+        let mut enabled = self.regs[USART_CTRLA] & 0xF0;
+        if (self.regs[USART_CTRLA] & 0x04) != 0 {
+            enabled |= 0x08;
+        }
+        (self.regs[USART_STATUS] & enabled & mask) != 0x00
     }
 }
 
 impl Clocked for Usart {
+    fn sleep(&mut self, mode: Option<SleepMode>) -> bool {
+        // Start-of-frame detection keeps the receiver running in standby
+        self.standby = mode == Some(SleepMode::Standby);
+        match mode {
+            Some(SleepMode::Standby) => self.is_sfden() & self.rxen(),
+            Some(SleepMode::PowerDown) => false,
+            _ => true,
+        }
+    }
+
     fn tick(&mut self, _time: u64) {
+        match self.cmode() {
+            UsartCmode::SYNC | UsartCmode::MSPI => {
+                self.sync_tick();
+                return;
+            }
+            UsartCmode::IRCOM => {
+                if self.ir_tx_pulse > 0 {
+                    self.ir_tx_pulse -= 1;
+                    if self.ir_tx_pulse == 0 {
+                        self.txd(Some(true));
+                    }
+                }
+            }
+            UsartCmode::ASYNC => {}
+        }
+
         // new Rx pinstate
-        let rx_port_pinstate = if self.mux_alt {
-            self.port_alt.borrow().get_pinstate(self.pins_alt[0])
-        } else {
-            self.port.borrow().get_pinstate(self.pins[0])
-        };
+        let mut rx_port_pinstate = self.rxd();
+        if self.cmode().eq(&UsartCmode::IRCOM) {
+            rx_port_pinstate = self.ir_decode(rx_port_pinstate);
+        }
         let rx_pinstate_new = if rx_port_pinstate {
             RxState::High
         } else {
@@ -287,43 +924,41 @@ impl Clocked for Usart {
         let (mut rx_accum_new, _) = self.rx_accum.overflowing_add(self.baud_inc());
         let (tx_accum_new, _) = self.tx_accum.overflowing_add(self.baud_inc());
 
-        if self.rxen() {
+        let autobaud = match self.mode() {
+            UsartMode::GENAUTO | UsartMode::LINAUTO => {
+                self.rxen() && self.autobaud(rx_port_pinstate)
+            }
+            _ => false,
+        };
+
+        if autobaud {
+            self.rx_state = UsartState::Idle;
+        } else if self.rxen() {
             match self.rx_state {
                 UsartState::Idle => {
                     if rx_pinstate_new.eq(&RxState::Low) & self.rx_pinstate.eq(&RxState::High) {
-                        self.rx_state = UsartState::Shift;
-                        self.rx_bit = 10; // 8N1
+                        self.rx_start();
                         rx_accum_new = 0x80000000; // half bit
-                        if self.rx_buf.len() > 1 {
-                            self.regs[USART_RXDATAH] |= 0x40; // Buffer overflow
+                        if self.standby & self.is_sfden() {
+                            self.regs[USART_STATUS] |= 0x10; // Set RXSIF
                         }
                     }
                 }
                 UsartState::Shift => {
                     if rx_accum_new < self.rx_accum {
-                        if rx_pinstate_new.eq(&RxState::High) {
-                            self.rx_reg |= 1;
-                        }
-                        self.rx_bit -= 1;
-                        if self.rx_bit == 0 {
+                        let level = rx_pinstate_new.eq(&RxState::High);
+                        if (self.rx_bit == 0) & level {
+                            // False start bit, e.g. a glitch on the line
                             self.rx_state = UsartState::Idle;
-                            self.rx_reg = self.rx_reg.reverse_bits(); // Stop bit now MSB
-                            let ferr = if (self.rx_reg & 0x80) == 0x80 {
-                                1 << 10
-                            } else {
-                                0u16
-                            };
-                            let perr = 0u16;
-                            self.rx_reg >>= 7; // D0 is now LSB
-                            self.rx_reg &= 0x00FF; // Adjust this if 9 bits required
-                            self.rx_buf.push_back(ferr | perr | self.rx_reg);
-                            self.regs[USART_RXDATAH] |= 0x80; // Set RXCIF (unread data)
-                            self.regs[USART_STATUS] |= 0x80; // Set RXCIF (unread data)
-                            if self.rx_buf.len() > 2 {
-                                self.rx_buf.pop_front(); // Overflow, discard oldest data
-                            }
                         } else {
-                            self.rx_reg <<= 1;
+                            if level {
+                                self.rx_reg |= 1 << self.rx_bit;
+                            }
+                            self.rx_bit += 1;
+                            if self.rx_bit == self.rx_frame_len() {
+                                self.rx_state = UsartState::Idle;
+                                self.receive();
+                            }
                         }
                     }
                 }
@@ -337,29 +972,18 @@ impl Clocked for Usart {
                 UsartState::Idle => {}
                 UsartState::Shift => {
                     if tx_accum_new < self.tx_accum {
-                        if self.mux_alt {
-                            self.port_alt
-                                .borrow_mut()
-                                .po_out(self.pins_alt[1], (self.tx_reg & 1) == 1);
+                        if self.tx_bit > 0 {
+                            self.tx_out((self.tx_reg & 1) == 1);
+                            self.tx_reg >>= 1;
+                            self.tx_bit -= 1;
+                        } else if let Some(data) = self.tx_buf.pop_front() {
+                            let (frame, len) = self.frame(data);
+                            self.tx_out(false); // start bit
+                            self.tx_reg = frame >> 1;
+                            self.tx_bit = len - 1;
+                            self.regs[USART_STATUS] |= 0x20; // Set DREIF
                         } else {
-                            self.port
-                                .borrow_mut()
-                                .po_out(self.pins[1], (self.tx_reg & 1) == 1);
-                        }
-                        self.tx_reg >>= 1;
-                        self.tx_bit -= 1;
-                        if self.tx_bit == 0 {
-                            if !self.tx_buf.is_empty() {
-                                self.tx_reg = (self.tx_buf.pop_front().unwrap() << 1) | 0x0200; // add start and stop bits
-                                self.tx_bit = 10; // check???
-                                if !self.dre() {
-                                    self.regs[USART_STATUS] |= 0x20; // Set DREIF
-                                }
-                            } else {
-                                self.tx_state = UsartState::Idle;
-                                self.tx_accum = 0;
-                                self.regs[USART_STATUS] |= 0x40; // Set TXCIF (buffer empty)
-                            }
+                            self.tx_complete();
                         }
                     }
                 }
@@ -374,3 +998,206 @@ impl Clocked for Usart {
         self.rx_pinstate = rx_pinstate_new;
     }
 }
+
+impl PinMux for Usart {
+    fn route(&mut self, route: u8) {
+        self.txd(None);
+        self.xck(None);
+        self.xck_level = false;
+        self.po_out(USART_PIN_XDIR, None);
+        self.mux = route;
+        if self.rs485().eq(&Rs485Mode::EXT) {
+            self.po_out(USART_PIN_XDIR, Some(self.tx_drive));
+        }
+        if self.txen() {
+            self.txd(Some(self.tx_level));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::sinkuart::SinkUART;
+    use crate::hardware::uartbridge::UartBridge;
+    use crate::hardware::Hardware;
+    use crate::nets::Net;
+
+    const PIN_TXD: u8 = 2;
+    const PIN_RXD: u8 = 3;
+    const NS_PER_TICK: u64 = 100; // 10 MHz CLK_PER
+    const BAUD_115200: u16 = 347; // 64 * 10 MHz / (16 * 115200)
+
+    // Collects the frames received without error by SinkUART
+    struct Received(Rc<RefCell<Vec<u8>>>);
+
+    impl UartBridge for Received {
+        fn read(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn write(&mut self, byte: u8) {
+            self.0.borrow_mut().push(byte);
+        }
+    }
+
+    // USART0 with TXD and RXD connected to an emulated UART host
+    struct Bench {
+        usart: Usart,
+        port: Rc<RefCell<Port>>,
+        nets: [Rc<RefCell<Net>>; 2],
+        host: SinkUART,
+        received: Rc<RefCell<Vec<u8>>>,
+        time: u64,
+    }
+
+    impl Bench {
+        fn new(ctrlb: u8, ctrlc: u8, baud: u16, host_baud: u32, host_format: &str) -> Self {
+            let port = Rc::new(RefCell::new(Port::new("PORTB".to_string())));
+            let txd = Rc::new(RefCell::new(Net::new("TXD".to_string())));
+            let rxd = Rc::new(RefCell::new(Net::new("RXD".to_string())));
+            port.borrow_mut().connect(PIN_TXD, Rc::clone(&txd));
+            port.borrow_mut().connect(PIN_RXD, Rc::clone(&rxd));
+            port.borrow_mut().write(0x00, 1 << PIN_TXD); // DIR
+
+            let mut host = SinkUART::new(
+                "HOST".to_string(),
+                Rc::clone(&txd),
+                Rc::clone(&rxd),
+                "uart.txt".to_string(),
+            );
+            let received = Rc::new(RefCell::new(Vec::new()));
+            host.set_bridge(Box::new(Received(Rc::clone(&received))));
+            host.event(0, &format!("baud {}", host_baud));
+            host.event(0, &format!("format {}", host_format));
+
+            let mut usart = Usart::new(
+                "USART0".to_string(),
+                Rc::clone(&port),
+                [PIN_RXD, PIN_TXD, 1, 0],
+                Rc::clone(&port),
+                [PIN_RXD, PIN_TXD, 1, 0],
+            );
+            usart.write(USART_BAUDL, baud as u8);
+            usart.write(USART_BAUDH, (baud >> 8) as u8);
+            usart.write(USART_CTRLC, ctrlc);
+            usart.write(USART_CTRLB, ctrlb);
+
+            let mut bench = Bench {
+                usart,
+                port,
+                nets: [txd, rxd],
+                host,
+                received,
+                time: 0,
+            };
+            bench.run(100_000); // Both lines idle high
+            bench
+        }
+
+        fn run(&mut self, ns: u64) {
+            let end = self.time + ns;
+            while self.time < end {
+                self.time += NS_PER_TICK;
+                for net in &self.nets {
+                    net.borrow_mut().update(self.time);
+                }
+                self.host.update(self.time);
+                self.port.borrow_mut().update(self.time);
+                self.usart.tick(self.time);
+            }
+        }
+
+        fn send(&mut self, event: &str) {
+            self.host.event(self.time, event);
+            self.run(200_000); // Longest frame at 115200 baud is 13 bits
+        }
+
+        fn read(&mut self) -> u16 {
+            // RXDATAH first, as RXDATAL is read last except in 9BITL mode
+            let high = self.usart.read(USART_RXDATAH).0;
+            let low = self.usart.read(USART_RXDATAL).0;
+            (u16::from(high) << 8) | u16::from(low)
+        }
+    }
+
+    #[test]
+    fn frame_formats() {
+        // Data bits and CHSIZE, using 9BITH for 9-bit frames
+        let sizes = [(5, 0x00), (6, 0x01), (7, 0x02), (8, 0x03), (9, 0x07)];
+        let parities = [('N', 0x00), ('E', 0x20), ('O', 0x30)];
+        let stops = [(1, 0x00), (2, 0x08)];
+        for (bits, chsize) in sizes {
+            for (parity, pmode) in parities {
+                for (stop, sbmode) in stops {
+                    let format = format!("{}{}{}", bits, parity, stop);
+                    let mut bench =
+                        Bench::new(0xC0, pmode | sbmode | chsize, BAUD_115200, 115200, &format);
+                    let mask = (1u16 << bits) - 1;
+                    for data in [0x1A5u16, 0x05A] {
+                        let data = data & mask;
+
+                        // USART to host, the host only accepts frames with valid parity and stop bits
+                        bench.usart.write(USART_TXDATAH, (data >> 8) as u8);
+                        bench.usart.write(USART_TXDATAL, data as u8);
+                        bench.run(200_000);
+                        assert_eq!(
+                            bench.received.borrow_mut().pop(),
+                            Some(data as u8),
+                            "{format} tx {data:03X}"
+                        );
+
+                        // Host to USART
+                        bench.send(&format!("{:X}", data));
+                        assert_eq!(bench.read(), 0x8000 | data, "{format} rx {data:03X}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn receive_errors() {
+        let mut bench = Bench::new(0xC0, 0x23, BAUD_115200, 115200, "8E1");
+        bench.send("ferr 41");
+        assert_eq!(bench.read(), 0x8441); // RXCIF, FERR
+        bench.send("perr 41");
+        assert_eq!(bench.read(), 0x8241); // RXCIF, PERR
+        bench.send("41");
+        assert_eq!(bench.read(), 0x8041);
+    }
+
+    #[test]
+    fn buffer_overflow() {
+        // Two frames fill the buffer and a third waits in the shift register,
+        // until it is lost to the start bit of a fourth
+        let mut bench = Bench::new(0xC0, 0x03, BAUD_115200, 115200, "8N1");
+        for data in ["31", "32", "33", "34"] {
+            bench.send(data);
+        }
+        assert_eq!(bench.read(), 0x8031);
+        assert_eq!(bench.read(), 0x8032);
+        assert_eq!(bench.read(), 0xC034); // RXCIF, BUFOVF
+        assert_eq!(bench.usart.read(USART_STATUS).0 & 0x80, 0x00);
+    }
+
+    #[test]
+    fn auto_baud() {
+        // GENAUTO at a nominal 115200 baud, the host sends at 100000 baud
+        let mut bench = Bench::new(0x84, 0x03, BAUD_115200, 100000, "8N1");
+        bench.send("break");
+        bench.send("55");
+        let baud = u16::from_le_bytes([
+            bench.usart.read(USART_BAUDL).0,
+            bench.usart.read(USART_BAUDH).0,
+        ]);
+        assert!((399..=401).contains(&baud), "BAUD {baud}"); // 64 * 10 MHz / (16 * 100000)
+        assert_eq!(bench.usart.read(USART_STATUS).0 & 0x0A, 0x02); // BDF, not ISFIF
+                                                                   // The break is received as a zero frame with FERR, the sync field is not received
+        assert_eq!(bench.read(), 0x8400);
+        assert_eq!(bench.usart.read(USART_STATUS).0 & 0x80, 0x00);
+
+        bench.send("41");
+        assert_eq!(bench.read(), 0x8041);
+    }
+}